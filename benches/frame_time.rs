@@ -0,0 +1,108 @@
+use ratatui::{backend::TestBackend, Terminal};
+use ratatui_type_and_scroll::{app::AppState, buffer::Buffer, ui::ui};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const FILE_SIZE: usize = 100 * 1024 * 1024;
+const FRAMES: usize = 200;
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join("ratatui-type-and-scroll-frame-time.txt");
+    write_sample_file(&path)?;
+
+    let start = Instant::now();
+    let buffer = Buffer::from_reader(BufReader::new(File::open(&path)?))?;
+    println!(
+        "loaded {} MB ({} lines) in {:?}",
+        FILE_SIZE / (1024 * 1024),
+        buffer.line_count(),
+        start.elapsed()
+    );
+    std::fs::remove_file(&path)?;
+
+    let mut state = AppState {
+        buffer,
+        ..Default::default()
+    };
+    let mut terminal = Terminal::new(TestBackend::new(120, 40))?;
+    let line_count = state.buffer.line_count();
+
+    for (name, position) in [
+        ("top", 0),
+        ("middle", line_count / 2),
+        ("bottom", line_count.saturating_sub(40)),
+    ] {
+        state.scroll_position = position;
+        let times = (0..FRAMES)
+            .map(|_| time_frame(&mut terminal, &mut state))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        report(name, &times);
+    }
+
+    let step = line_count / FRAMES;
+    let times = (0..FRAMES)
+        .map(|i| {
+            state.scroll_position = i * step;
+            time_frame(&mut terminal, &mut state)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    report("scrolling", &times);
+
+    let start = Instant::now();
+    state.toggle_wrap();
+    terminal.draw(|frame| ui(frame, &mut state))?;
+    println!(
+        "wrapped {} rows in {:?}",
+        state.row_count(),
+        start.elapsed()
+    );
+    let step = state.row_count() / FRAMES;
+    let times = (0..FRAMES)
+        .map(|i| {
+            state.scroll_position = i * step;
+            time_frame(&mut terminal, &mut state)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    report("wrapped", &times);
+
+    Ok(())
+}
+
+fn write_sample_file(path: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut written = 0;
+    let mut line_number = 0;
+    while written < FILE_SIZE {
+        let line = format!(
+            "{line_number:>8}: The quick brown fox jumps over the lazy dog, again and again.\n"
+        );
+        writer.write_all(line.as_bytes())?;
+        written += line.len();
+        line_number += 1;
+    }
+    writer.flush()
+}
+
+fn time_frame(
+    terminal: &mut Terminal<TestBackend>,
+    state: &mut AppState,
+) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    terminal.draw(|frame| ui(frame, state))?;
+    Ok(start.elapsed())
+}
+
+fn report(name: &str, times: &[Duration]) {
+    let total: Duration = times.iter().sum();
+    let max = times.iter().max().copied().unwrap_or_default();
+    println!(
+        "{name:>10}: mean {:?}, max {:?} over {} frames",
+        total / times.len() as u32,
+        max,
+        times.len()
+    );
+}
@@ -0,0 +1,1328 @@
+use crate::{
+    buffer::{Buffer, Cursor, LineEdit, Selection},
+    clipboard, file,
+    history::{EditKind, History, Snapshot},
+    keymap::Action,
+    kill_ring::KillRing,
+    prompt::{Prompt, PromptKind},
+    search::{Replacement, Search, SearchOptions},
+    syntax::Highlighter,
+    vim::Vim,
+    wrap::{self, Row, WrapIndex},
+};
+use ratatui::layout::{Position, Rect};
+use std::{
+    ops::Range,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+
+/// Text that was yanked or deleted, for pasting back. Linewise text is
+/// pasted as whole lines.
+#[derive(Debug, Clone, Default)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct AppState {
+    pub scroll_position: usize,
+    pub horizontal_scroll: usize,
+    pub viewport_height: usize,
+    pub content_width: usize,
+    pub text_area: Rect,
+    pub scrollbar_area: Rect,
+    pub dragging_scrollbar: bool,
+    pub dragging_selection: bool,
+    pub wrap: bool,
+    pub line_numbers: bool,
+    /// Shows line numbers relative to the cursor's line, when line numbers
+    /// are shown.
+    pub relative_numbers: bool,
+    pub wrap_index: WrapIndex,
+    pub buffer: Buffer,
+    pub history: History,
+    pub path: Option<PathBuf>,
+    pub modified: bool,
+    /// The buffer revision that was last loaded or saved, which undo and
+    /// redo compare against to tell whether anything is unsaved.
+    pub saved_revision: Option<u64>,
+    pub message: Option<String>,
+    pub message_expiry: Option<Instant>,
+    pub prompt: Option<Prompt>,
+    pub dirty: bool,
+    pub quit: bool,
+    pub register: Register,
+    pub kill_ring: KillRing,
+    pub vim: Option<Vim>,
+    pub search: Option<Search>,
+    /// Where the cursor and scroll position were when the search prompt
+    /// opened, to go back to if it is cancelled.
+    pub search_origin: Option<(Cursor, usize)>,
+    pub search_options: SearchOptions,
+    pub replacement: Option<Replacement>,
+    /// Syntax highlighting, if the file's language is known.
+    pub highlighter: Option<Highlighter>,
+    /// The action run before the current one, which decides whether kills
+    /// append and whether `yank_pop` applies.
+    pub last_action: Option<Action>,
+}
+
+impl AppState {
+    pub fn edit(&mut self, kind: EditKind, f: impl FnOnce(&mut Buffer)) {
+        let before = self.snapshot();
+        self.buffer.edit_as_one(f);
+        // Edits that change nothing, like Backspace at the very start, are
+        // not worth an undo step.
+        let base_revision = before.buffer.revision();
+        if self.buffer.revision() != base_revision {
+            let edit = self
+                .buffer
+                .last_edit()
+                .filter(|edit| edit.base_revision == base_revision)
+                .cloned();
+            self.history.record(kind, before, edit);
+        }
+        self.modified = self.saved_revision != Some(self.buffer.revision());
+        self.refresh_wrap();
+        if let Some(search) = &mut self.search {
+            search.update(&self.buffer);
+        }
+        self.follow_cursor_column();
+        self.scroll_to_cursor();
+    }
+
+    /// Runs an edit typed by the user. A selection is deleted first, and
+    /// the edit itself only runs if it inserts something.
+    pub fn edit_selection(&mut self, kind: EditKind, f: impl FnOnce(&mut Buffer)) {
+        let Some((start, end)) = self.buffer.selection_range() else {
+            return self.edit(kind, f);
+        };
+        self.history.close_group();
+        self.edit(kind, |buffer| {
+            buffer.delete_between(start, end);
+            if kind != EditKind::Delete {
+                f(buffer);
+            }
+        });
+    }
+
+    /// Moves the cursor, which ends a selection made with Shift or the
+    /// mouse.
+    pub fn move_cursor(&mut self, f: impl FnOnce(&mut Buffer)) {
+        self.history.close_group();
+        f(&mut self.buffer);
+        if self.buffer.selection().is_some_and(|s| !s.inclusive) {
+            self.buffer.set_selection(None);
+        }
+        self.follow_cursor_column();
+        self.scroll_to_cursor();
+    }
+
+    /// Moves the cursor back onto the last char of its line, as Vim's
+    /// Normal mode never leaves it after the end of a line, nor on the empty
+    /// line after a final line break.
+    pub fn keep_cursor_on_char(&mut self) {
+        let cursor = self.buffer.cursor();
+        let line = cursor
+            .line
+            .min(self.buffer.lines_in_file().saturating_sub(1));
+        let column = cursor
+            .column
+            .min(self.buffer.line_len(line).saturating_sub(1));
+        if (Cursor { line, column }) != cursor {
+            self.move_cursor(|buffer| buffer.move_to(line, column));
+        }
+    }
+
+    /// Runs a cursor motion that extends the selection, starting one at the
+    /// cursor if there is none.
+    pub fn extend_selection(&mut self, f: impl FnOnce(&mut Self)) {
+        let anchor = self
+            .buffer
+            .selection()
+            .map_or(self.buffer.cursor(), |selection| selection.anchor);
+        f(self);
+        self.buffer.set_selection(Some(Selection {
+            anchor,
+            inclusive: false,
+        }));
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((snapshot, edit)) = self.history.undo(self.snapshot()) {
+            self.restore(snapshot, edit);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some((snapshot, edit)) = self.history.redo(self.snapshot()) {
+            self.restore(snapshot, edit);
+        }
+    }
+
+    pub fn start_selection(&mut self, inclusive: bool) {
+        self.buffer.set_selection(Some(Selection {
+            anchor: self.buffer.cursor(),
+            inclusive,
+        }));
+    }
+
+    /// Copies the selection into the register, leaving it selected.
+    pub fn copy_selection(&mut self) {
+        if let Some((start, end)) = self.buffer.selection_range() {
+            self.register = Register {
+                text: self.buffer.text_between(start, end),
+                linewise: false,
+            };
+        }
+    }
+
+    /// Copies the selection to the system clipboard as well as the register.
+    pub fn copy(&mut self) {
+        if self.buffer.selection_range().is_none() {
+            return;
+        }
+        self.copy_selection();
+        if let Err(e) = clipboard::copy(&self.register.text) {
+            self.set_message(format!("Could not copy to the clipboard: {e}"));
+        }
+    }
+
+    pub fn cut(&mut self) {
+        if self.buffer.selection_range().is_some() {
+            self.copy();
+            self.edit_selection(EditKind::Delete, |_| ());
+        }
+    }
+
+    /// Pastes what was last copied or cut, over the selection if there is
+    /// one. Terminals rarely let programs read the system clipboard, so this
+    /// uses the register; text pasted from other programs arrives as typing.
+    pub fn paste_clipboard(&mut self) {
+        if self.register.text.is_empty() {
+            return;
+        }
+        let text = self.register.text.clone();
+        self.paste_text(&text);
+    }
+
+    /// Inserts `text` over the selection, undoing as one step.
+    pub fn paste_text(&mut self, text: &str) {
+        self.edit_selection(EditKind::Paste, |buffer| buffer.insert_text(text));
+    }
+
+    pub fn yank_selection(&mut self) {
+        let range = self.buffer.selection_range();
+        self.copy_selection();
+        self.buffer.set_selection(None);
+        if let Some((start, _)) = range {
+            self.move_cursor(|buffer| buffer.move_to(start.line, start.column));
+        }
+    }
+
+    pub fn delete_selection(&mut self) {
+        if self.buffer.selection_range().is_some() {
+            self.copy_selection();
+            self.edit_selection(EditKind::Delete, |_| ());
+        }
+        self.buffer.set_selection(None);
+    }
+
+    /// Yanks `count` lines starting at the cursor's.
+    pub fn yank_lines(&mut self, count: usize) {
+        let line = self.buffer.cursor().line;
+        self.register = Register {
+            text: self.buffer.lines_text(line..line.saturating_add(count)),
+            linewise: true,
+        };
+    }
+
+    /// Deletes `count` lines starting at the cursor's into the register.
+    pub fn delete_lines(&mut self, count: usize) {
+        self.yank_lines(count);
+        let line = self.buffer.cursor().line;
+        self.edit(EditKind::Delete, |buffer| {
+            buffer.delete_lines(line..line.saturating_add(count))
+        });
+    }
+
+    /// Pastes the register after the cursor, or below the cursor's line
+    /// when it holds whole lines. `before` pastes at the cursor or above
+    /// its line instead.
+    pub fn paste(&mut self, before: bool) {
+        if self.register.text.is_empty() {
+            return;
+        }
+        let Register { text, linewise } = self.register.clone();
+        let cursor = self.buffer.cursor();
+        self.edit(EditKind::Paste, |buffer| {
+            if !linewise {
+                if !before && cursor.column < buffer.line_len(cursor.line) {
+                    buffer.move_right();
+                }
+                buffer.insert_text(&text);
+                return;
+            }
+            let line = if before { cursor.line } else { cursor.line + 1 };
+            if line < buffer.line_count() {
+                buffer.move_to(line, 0);
+                buffer.insert_text(&text);
+            } else {
+                // There is no line to paste in front of, so the line break
+                // goes first.
+                buffer.move_document_end();
+                buffer.insert_line_break();
+                buffer.insert_text(text.strip_suffix('\n').unwrap_or(&text));
+            }
+            buffer.move_to(line, 0);
+        });
+    }
+
+    /// Kills to the end of the line, or the line break when the cursor is
+    /// already there. Consecutive kills join into one kill ring entry.
+    pub fn kill_line(&mut self) {
+        let cursor = self.buffer.cursor();
+        let line_len = self.buffer.line_len(cursor.line);
+        let end = if cursor.column < line_len {
+            Cursor {
+                column: line_len,
+                ..cursor
+            }
+        } else {
+            self.buffer.position_after(cursor)
+        };
+        if end == cursor {
+            return;
+        }
+        let append = self.last_action == Some(Action::KillLine);
+        self.kill_ring
+            .kill(self.buffer.text_between(cursor, end), append);
+        self.edit(EditKind::Delete, |buffer| {
+            buffer.delete_between(cursor, end)
+        });
+    }
+
+    /// Inserts the most recent kill at the cursor.
+    pub fn yank(&mut self) {
+        let Some(text) = self.kill_ring.get(0).map(str::to_owned) else {
+            return;
+        };
+        let start = self.buffer.cursor();
+        self.edit(EditKind::Paste, |buffer| buffer.insert_text(&text));
+        self.kill_ring.last_yank = Some((start, self.buffer.cursor(), 0));
+    }
+
+    /// Ends the run of kills or yanks, after something other than a kill or
+    /// yank moved the cursor or changed the text.
+    pub fn forget_last_action(&mut self) {
+        self.last_action = None;
+        self.kill_ring.last_yank = None;
+    }
+
+    /// Replaces the text just yanked with the kill before it.
+    pub fn yank_pop(&mut self) {
+        let yanked = matches!(self.last_action, Some(Action::Yank | Action::YankPop));
+        let cursor = self.buffer.cursor();
+        let last_yank = self
+            .kill_ring
+            .last_yank
+            .filter(|&(start, end, _)| yanked && end == cursor && start <= end);
+        let Some((start, end, back)) = last_yank else {
+            self.set_message("Previous command was not a yank");
+            return;
+        };
+        let Some(text) = self.kill_ring.get(back + 1).map(str::to_owned) else {
+            return;
+        };
+        self.edit(EditKind::Paste, |buffer| {
+            buffer.delete_between(start, end);
+            buffer.insert_text(&text);
+        });
+        self.kill_ring.last_yank = Some((start, self.buffer.cursor(), back + 1));
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        let (top_line, _) = self.line_at_row(self.scroll_position);
+        self.wrap = !self.wrap;
+        self.horizontal_scroll = 0;
+        self.refresh_wrap();
+        self.scroll_position = self.first_row(top_line).min(self.max_scroll());
+    }
+
+    /// The width of the line number gutter: room for the largest line
+    /// number, and a space.
+    pub fn gutter_width(&self) -> u16 {
+        if !self.line_numbers {
+            return 0;
+        }
+        let digits = self.buffer.line_count().to_string().len();
+        u16::try_from(digits.max(3) + 1).unwrap_or(u16::MAX)
+    }
+
+    pub fn toggle_line_numbers(&mut self) {
+        self.line_numbers = !self.line_numbers;
+    }
+
+    /// Switches between absolute and relative line numbers, showing them if
+    /// they were hidden.
+    pub fn toggle_relative_numbers(&mut self) {
+        self.relative_numbers = !self.relative_numbers || !self.line_numbers;
+        self.line_numbers = true;
+    }
+
+    /// The number of visual rows in the document. Without wrapping this is
+    /// the line count.
+    pub fn row_count(&self) -> usize {
+        if self.wrap {
+            self.wrap_index.row_count()
+        } else {
+            self.buffer.line_count()
+        }
+    }
+
+    pub fn first_row(&self, line: usize) -> usize {
+        if self.wrap {
+            self.wrap_index.first_row(line)
+        } else {
+            line
+        }
+    }
+
+    /// The line containing visual `row`, and the row's offset in that line.
+    pub fn line_at_row(&self, row: usize) -> (usize, usize) {
+        if self.wrap {
+            self.wrap_index.line_at_row(row)
+        } else {
+            (row, 0)
+        }
+    }
+
+    /// The char index at which each visual row of `line` starts.
+    pub fn row_starts(&self, line: &str) -> Vec<usize> {
+        if self.wrap {
+            wrap::row_starts(line, usize::from(self.text_area.width))
+        } else {
+            vec![0]
+        }
+    }
+
+    /// The rows shown in the viewport, starting at the scroll position.
+    pub fn visible_rows(&self) -> Vec<Row> {
+        let (first_line, mut skip) = self.line_at_row(self.scroll_position);
+        let mut rows = Vec::with_capacity(self.viewport_height);
+        if self.viewport_height == 0 {
+            return rows;
+        }
+        for (line, text) in (first_line..).zip(self.buffer.lines_from(first_line)) {
+            let starts = self.row_starts(&text);
+            let len = text.chars().count();
+            for (i, &start) in starts.iter().enumerate().skip(skip) {
+                let end = starts.get(i + 1).copied().unwrap_or(len);
+                rows.push(Row {
+                    line,
+                    range: start..end,
+                    text: text.chars().skip(start).take(end - start).collect(),
+                });
+                if rows.len() == self.viewport_height {
+                    return rows;
+                }
+            }
+            skip = 0;
+        }
+        rows
+    }
+
+    /// The cursor's visual row in the document and its display column.
+    pub fn cursor_screen_offset(&self) -> (usize, usize) {
+        let cursor = self.buffer.cursor();
+        let line = self.buffer.line(cursor.line);
+        let (row, range) = self.row_range(&line, cursor.column);
+        let x = wrap::display_width(&line, range.start..cursor.column);
+        (self.first_row(cursor.line) + row, x)
+    }
+
+    /// Moves the cursor to the start of its visual row.
+    pub fn cursor_home(&mut self) {
+        let cursor = self.buffer.cursor();
+        let line = self.buffer.line(cursor.line);
+        let (_, range) = self.row_range(&line, cursor.column);
+        self.move_cursor(|buffer| buffer.move_to(cursor.line, range.start));
+    }
+
+    /// Moves the cursor to the end of its visual row.
+    pub fn cursor_end(&mut self) {
+        let cursor = self.buffer.cursor();
+        let line = self.buffer.line(cursor.line);
+        let (_, range) = self.row_range(&line, cursor.column);
+        let column = row_end(&line, range);
+        self.move_cursor(|buffer| buffer.move_to(cursor.line, column));
+    }
+
+    /// The largest scroll position at which the viewport is still filled,
+    /// i.e. the one that puts the last row on the bottom of the viewport.
+    pub fn max_scroll(&self) -> usize {
+        self.row_count().saturating_sub(self.viewport_height)
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll_position = self
+            .scroll_position
+            .saturating_add(rows)
+            .min(self.max_scroll());
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll_position = self.scroll_position.saturating_sub(rows);
+    }
+
+    /// The widest horizontal scroll that still shows part of the widest
+    /// visible line. Wrapped text never scrolls horizontally.
+    pub fn max_horizontal_scroll(&self) -> usize {
+        if self.wrap {
+            0
+        } else {
+            self.content_width
+                .saturating_sub(usize::from(self.text_area.width))
+        }
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.horizontal_scroll = self
+            .horizontal_scroll
+            .saturating_add(columns)
+            .min(self.max_horizontal_scroll());
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.horizontal_scroll = self.horizontal_scroll.saturating_sub(columns);
+    }
+
+    /// Scrolls horizontally just far enough for the cursor to be visible.
+    pub fn follow_cursor_column(&mut self) {
+        if self.wrap {
+            return;
+        }
+        let (_, x) = self.cursor_screen_offset();
+        let width = usize::from(self.text_area.width);
+        if x < self.horizontal_scroll {
+            self.horizontal_scroll = x;
+        } else if x >= self.horizontal_scroll + width {
+            self.horizontal_scroll = (x + 1).saturating_sub(width);
+        }
+    }
+
+    /// Scrolls just far enough for the cursor row to be visible.
+    pub fn scroll_to_cursor(&mut self) {
+        let (row, _) = self.cursor_screen_offset();
+        if row < self.scroll_position {
+            self.scroll_position = row;
+        } else if row >= self.scroll_position + self.viewport_height {
+            self.scroll_position = (row + 1).saturating_sub(self.viewport_height);
+        }
+    }
+
+    /// Moves the cursor to the 1-based `line` and scrolls it to the top of
+    /// the viewport, or as close as the end of the document allows.
+    pub fn go_to_line(&mut self, line: usize) {
+        self.history.close_group();
+        self.buffer.move_to_line(line.saturating_sub(1));
+        let row = self.first_row(self.buffer.cursor().line);
+        self.scroll_position = row.min(self.max_scroll());
+        self.follow_cursor_column();
+    }
+
+    /// Places the cursor at a screen position, if it is inside the text area.
+    pub fn click_text(&mut self, position: Position) {
+        if !self.text_area.contains(position) {
+            return;
+        }
+        let row = self.scroll_position + usize::from(position.y - self.text_area.y);
+        if row >= self.row_count() {
+            self.move_cursor(Buffer::move_document_end);
+            return;
+        }
+        let x = usize::from(position.x - self.text_area.x) + self.horizontal_scroll;
+        let (line, column) = self.position_at(row, x);
+        self.move_cursor(|buffer| buffer.move_to(line, column));
+    }
+
+    /// Moves the cursor up one visual row, keeping its display column.
+    pub fn cursor_up(&mut self) {
+        let (row, x) = self.cursor_screen_offset();
+        if row > 0 {
+            let (line, column) = self.position_at(row - 1, x);
+            self.move_cursor(|buffer| buffer.move_to(line, column));
+        }
+    }
+
+    /// Moves the cursor down one visual row, keeping its display column.
+    pub fn cursor_down(&mut self) {
+        let (row, x) = self.cursor_screen_offset();
+        if row + 1 < self.row_count() {
+            let (line, column) = self.position_at(row + 1, x);
+            self.move_cursor(|buffer| buffer.move_to(line, column));
+        }
+    }
+
+    /// Scrolls in proportion to where `row` falls along the scrollbar track,
+    /// which runs between the two arrow heads.
+    pub fn scroll_to_track_row(&mut self, row: u16) {
+        let track_start = self.scrollbar_area.y + 1;
+        let track_length = self.scrollbar_area.height.saturating_sub(2);
+        if track_length < 2 {
+            return;
+        }
+        let offset = usize::from(row.saturating_sub(track_start).min(track_length - 1));
+        let last_offset = usize::from(track_length - 1);
+        self.scroll_position = (offset * self.max_scroll() + last_offset / 2) / last_offset;
+    }
+
+    pub fn submit_prompt(&mut self, prompt: Prompt) {
+        match prompt.kind {
+            PromptKind::GoToLine => match prompt.input.trim().parse() {
+                Ok(line) => self.go_to_line(line),
+                Err(_) => self.set_message(format!("Invalid line number: {}", prompt.input)),
+            },
+            PromptKind::ConfirmQuit => self.quit = prompt.input.eq_ignore_ascii_case("y"),
+            PromptKind::Command => self.run_command(prompt.input.trim()),
+            PromptKind::Search => {
+                self.search_origin = None;
+                if prompt.input.is_empty() {
+                    self.search = None;
+                }
+            }
+            PromptKind::ReplacePattern => {
+                if self.find_for_replace(&prompt.input, self.search_options) {
+                    self.prompt = Some(Prompt::new(PromptKind::ReplaceWith));
+                }
+            }
+            PromptKind::ReplaceWith => {
+                self.replacement = Some(Replacement {
+                    template: prompt.input,
+                    count: 0,
+                });
+                self.next_replacement(Some(Cursor::default()));
+            }
+            PromptKind::ConfirmReplace => self.answer_replace(&prompt.input.to_lowercase()),
+        }
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        let Some(prompt) = self.prompt.take() else {
+            return;
+        };
+        match prompt.kind {
+            PromptKind::Search => {
+                self.search = None;
+                if let Some((cursor, scroll_position)) = self.search_origin.take() {
+                    self.move_cursor(|buffer| buffer.move_to(cursor.line, cursor.column));
+                    self.scroll_position = scroll_position;
+                }
+            }
+            PromptKind::ReplaceWith => self.search = None,
+            PromptKind::ConfirmReplace => self.finish_replace(),
+            _ => (),
+        }
+    }
+
+    /// Flips one of the search options, searching again if the search
+    /// prompt is open.
+    pub fn toggle_search_option(&mut self, f: impl FnOnce(&mut SearchOptions)) {
+        f(&mut self.search_options);
+        self.set_message(self.search_options.describe());
+        if self
+            .prompt
+            .as_ref()
+            .is_some_and(|p| p.kind == PromptKind::Search)
+        {
+            self.search = None;
+        }
+        self.preview_search();
+    }
+
+    pub fn start_search(&mut self) {
+        self.search_origin = Some((self.buffer.cursor(), self.scroll_position));
+        self.search = None;
+        self.prompt = Some(Prompt::new(PromptKind::Search));
+    }
+
+    /// Searches for what has been typed into the search prompt so far,
+    /// moving to the first match after where the search started.
+    pub fn preview_search(&mut self) {
+        let Some(prompt) = self
+            .prompt
+            .as_ref()
+            .filter(|p| p.kind == PromptKind::Search)
+        else {
+            return;
+        };
+        let Some((origin, scroll_position)) = self.search_origin else {
+            return;
+        };
+        if prompt.input.is_empty() {
+            self.search = None;
+            self.message = None;
+            self.message_expiry = None;
+            self.move_cursor(|buffer| buffer.move_to(origin.line, origin.column));
+            self.scroll_position = scroll_position;
+            return;
+        }
+        let regex = match self.search_options.build(&prompt.input, false) {
+            Ok(regex) => regex,
+            Err(e) => return self.set_message(regex_error(&e)),
+        };
+        // Typing more of the query only needs the lines that matched before.
+        let mut search = match self.search.take() {
+            Some(previous)
+                if !self.search_options.whole_word && prompt.input.starts_with(&previous.query) =>
+            {
+                previous.narrow(&prompt.input, regex, &self.buffer)
+            }
+            _ => Search::new(&prompt.input, regex, &self.buffer),
+        };
+        let found = search.select_at(origin);
+        self.set_message(search.status());
+        self.search = Some(search);
+        match found {
+            Some(start) => {
+                self.move_cursor(|buffer| buffer.move_to(start.line, start.column));
+            }
+            None => {
+                self.move_cursor(|buffer| buffer.move_to(origin.line, origin.column));
+                self.scroll_position = scroll_position;
+            }
+        }
+    }
+
+    /// Moves to the next match of the current search, or the previous one.
+    pub fn search_next(&mut self, backwards: bool) {
+        let Some(search) = &mut self.search else {
+            self.set_message("No search");
+            return;
+        };
+        search.update(&self.buffer);
+        let found = search.select_next(self.buffer.cursor(), backwards);
+        let status = search.status();
+        self.set_message(status);
+        if let Some(start) = found {
+            self.move_cursor(|buffer| buffer.move_to(start.line, start.column));
+        }
+    }
+
+    /// Starts a find-and-replace through the whole document by asking what
+    /// to replace.
+    pub fn start_replace(&mut self) {
+        self.prompt = Some(Prompt::new(PromptKind::ReplacePattern));
+    }
+
+    /// Searches for the regex `pattern` to replace. Returns whether there
+    /// is anything to replace.
+    fn find_for_replace(&mut self, pattern: &str, options: SearchOptions) -> bool {
+        match options.build(pattern, true) {
+            Ok(regex) => {
+                let search = Search::new(pattern, regex, &self.buffer);
+                let found = !search.matches().is_empty();
+                if found {
+                    self.set_message(count_matches(search.matches().len()));
+                    self.search = Some(search);
+                } else {
+                    self.set_message(search.status());
+                }
+                found
+            }
+            Err(e) => {
+                self.set_message(regex_error(&e));
+                false
+            }
+        }
+    }
+
+    /// Moves to the first match at or after `from` and asks whether to
+    /// replace it. Finishes when there are no more, or no progress is made.
+    fn next_replacement(&mut self, from: Option<Cursor>) {
+        let found = match (&mut self.search, from) {
+            (Some(search), Some(from)) => {
+                search.update(&self.buffer);
+                search.select_from(from)
+            }
+            _ => None,
+        };
+        let Some(start) = found else {
+            return self.finish_replace();
+        };
+        self.move_cursor(|buffer| buffer.move_to(start.line, start.column));
+        self.prompt = Some(Prompt::new(PromptKind::ConfirmReplace));
+    }
+
+    fn answer_replace(&mut self, answer: &str) {
+        let Some((start, end)) = self.search.as_ref().and_then(Search::current) else {
+            return self.finish_replace();
+        };
+        match answer {
+            "y" => {
+                let resume = self.replace_match(start, end);
+                self.next_replacement(self.after_match(start, end, resume));
+            }
+            "n" => self.next_replacement(self.after_match(start, end, end)),
+            "a" => {
+                self.replace_all(start, false);
+                self.finish_replace();
+            }
+            _ => self.finish_replace(),
+        }
+    }
+
+    /// Where to look for the match after the one from `start` to `end`,
+    /// when the text after it now starts at `resume`. Empty matches move on
+    /// by a char, so they are not found again.
+    fn after_match(&self, start: Cursor, end: Cursor, resume: Cursor) -> Option<Cursor> {
+        if end > start {
+            return Some(resume);
+        }
+        let next = self.buffer.position_after(resume);
+        (next > resume).then_some(next)
+    }
+
+    /// Replaces one match and returns where the replacement ends.
+    fn replace_match(&mut self, start: Cursor, end: Cursor) -> Cursor {
+        let (Some(search), Some(replacement)) = (&self.search, &mut self.replacement) else {
+            return end;
+        };
+        let text = search.replacement(&self.buffer, start, &replacement.template);
+        replacement.count += 1;
+        self.edit(EditKind::Paste, |buffer| {
+            buffer.delete_between(start, end);
+            buffer.insert_text(&text);
+        });
+        self.buffer.cursor()
+    }
+
+    /// Replaces every match from `from` on as one edit, so that a single
+    /// undo brings them all back. With `first_in_line`, only the first
+    /// match in each line is replaced.
+    fn replace_all(&mut self, from: Cursor, first_in_line: bool) {
+        let (Some(search), Some(replacement)) = (&mut self.search, &mut self.replacement) else {
+            return;
+        };
+        search.update(&self.buffer);
+        let mut last_line = None;
+        let replacements: Vec<_> = search
+            .matches()
+            .iter()
+            .filter(|(start, _)| *start >= from)
+            .filter(|(start, _)| {
+                !first_in_line || last_line.replace(start.line) != Some(start.line)
+            })
+            .map(|&(start, end)| {
+                let text = search.replacement(&self.buffer, start, &replacement.template);
+                (start, end, text)
+            })
+            .collect();
+        if replacements.is_empty() {
+            return;
+        }
+        replacement.count += replacements.len();
+        // Going backwards keeps the positions of the earlier matches valid.
+        self.edit(EditKind::Paste, |buffer| {
+            for (start, end, text) in replacements.iter().rev() {
+                buffer.delete_between(*start, *end);
+                buffer.insert_text(text);
+            }
+        });
+    }
+
+    fn finish_replace(&mut self) {
+        self.search = None;
+        if let Some(replacement) = self.replacement.take() {
+            self.set_message(format!("Replaced {}", count_matches(replacement.count)));
+        }
+    }
+
+    /// Runs Vim's `:%s/pattern/replacement/flags`. Flags are `g` for every
+    /// match in a line, `c` to confirm each one and `i`/`I` to ignore or
+    /// match case.
+    fn substitute(&mut self, command: &str) {
+        let Some((pattern, template, flags)) = parse_substitute(command) else {
+            return self.set_message(format!("Invalid substitute command: {command}"));
+        };
+        let mut options = self.search_options;
+        for flag in flags.chars() {
+            match flag {
+                'i' => options.ignore_case = true,
+                'I' => options.ignore_case = false,
+                'g' | 'c' => (),
+                _ => return self.set_message(format!("Invalid flag: {flag}")),
+            }
+        }
+        if !self.find_for_replace(&pattern, options) {
+            return;
+        }
+        self.replacement = Some(Replacement { template, count: 0 });
+        if flags.contains('c') {
+            self.next_replacement(Some(Cursor::default()));
+        } else {
+            self.replace_all(Cursor::default(), !flags.contains('g'));
+            self.finish_replace();
+        }
+    }
+
+    /// Runs a Vim-style `:` command.
+    fn run_command(&mut self, command: &str) {
+        match command {
+            "" => (),
+            "w" => self.save(),
+            "q" if self.modified => {
+                self.set_message("No write since last change (add ! to override)");
+            }
+            "q" | "q!" => self.quit = true,
+            "wq" | "x" => {
+                self.save();
+                self.quit = !self.modified;
+            }
+            "set number" | "set nu" => self.line_numbers = true,
+            "set nonumber" | "set nonu" => self.line_numbers = false,
+            "set relativenumber" | "set rnu" => {
+                self.line_numbers = true;
+                self.relative_numbers = true;
+            }
+            "set norelativenumber" | "set nornu" => self.relative_numbers = false,
+            _ if command.starts_with("%s") => self.substitute(&command[2..]),
+            _ => match command.parse() {
+                Ok(line) => self.go_to_line(line),
+                Err(_) => self.set_message(format!("Not an editor command: {command}")),
+            },
+        }
+    }
+
+    /// Lays the text out for a new screen size, keeping the top line in
+    /// place where possible and the cursor on screen.
+    pub fn resize(&mut self, text_area: Rect, scrollbar_area: Rect) {
+        let (top_line, offset) = self.line_at_row(self.scroll_position);
+        self.scrollbar_area = scrollbar_area;
+        self.set_viewport(text_area);
+        let last_row_of_line = self.first_row(top_line + 1).saturating_sub(1);
+        let row = (self.first_row(top_line) + offset).min(last_row_of_line);
+        self.scroll_position = row.min(self.max_scroll());
+        self.scroll_to_cursor();
+        self.follow_cursor_column();
+    }
+
+    pub fn set_viewport(&mut self, area: Rect) {
+        self.text_area = area;
+        self.viewport_height = usize::from(area.height);
+        self.refresh_wrap();
+        self.scroll_position = self.scroll_position.min(self.max_scroll());
+    }
+
+    /// Records the width of the widest visible row, plus a column for the
+    /// cursor after its end, which bounds horizontal scrolling.
+    pub fn set_content_width(&mut self, rows: &[Row]) {
+        self.content_width = rows
+            .iter()
+            .map(|row| wrap::str_width(&row.text) + 1)
+            .max()
+            .unwrap_or(0);
+    }
+
+    /// Shows a message until `MESSAGE_DURATION` has passed.
+    pub fn set_message(&mut self, message: impl Into<String>) {
+        self.message = Some(message.into());
+        self.message_expiry = Some(Instant::now() + MESSAGE_DURATION);
+    }
+
+    pub fn expire_message(&mut self) {
+        if self
+            .message_expiry
+            .is_some_and(|expiry| expiry <= Instant::now())
+        {
+            self.message = None;
+            self.message_expiry = None;
+        }
+    }
+
+    /// Quits right away when everything is saved, and asks first otherwise.
+    pub fn request_quit(&mut self) {
+        if self.modified {
+            self.prompt = Some(Prompt::new(PromptKind::ConfirmQuit));
+        } else {
+            self.quit = true;
+        }
+    }
+
+    pub fn save(&mut self) {
+        let Some(path) = &self.path else {
+            self.set_message("No file name");
+            return;
+        };
+        match file::save(path, &self.buffer) {
+            Ok(()) => {
+                self.modified = false;
+                self.saved_revision = Some(self.buffer.revision());
+                match self.buffer.lines_in_file() {
+                    1 => self.set_message("Saved 1 line"),
+                    lines => self.set_message(format!("Saved {lines} lines")),
+                }
+            }
+            Err(e) => self.set_message(format!("Save failed: {e}")),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            buffer: self.buffer.clone(),
+            scroll_position: self.scroll_position,
+        }
+    }
+
+    /// Swaps in an earlier or later state. `edit` is how the text changes
+    /// on the way, without which caches of the text are rebuilt.
+    fn restore(&mut self, snapshot: Snapshot, edit: Option<LineEdit>) {
+        self.buffer = snapshot.buffer;
+        self.buffer.set_last_edit(edit);
+        self.buffer.set_selection(None);
+        self.scroll_position = snapshot.scroll_position;
+        self.modified = self.saved_revision != Some(self.buffer.revision());
+        self.refresh_wrap();
+        self.follow_cursor_column();
+    }
+
+    fn refresh_wrap(&mut self) {
+        if self.wrap {
+            self.wrap_index
+                .update(&self.buffer, usize::from(self.text_area.width));
+        }
+    }
+
+    /// The line and column of the char drawn at display column `x` of
+    /// visual `row`, or of the row's last position if it is narrower.
+    fn position_at(&self, row: usize, x: usize) -> (usize, usize) {
+        let (line, offset) = self.line_at_row(row);
+        let text = self.buffer.line(line);
+        let starts = self.row_starts(&text);
+        let end = starts
+            .get(offset + 1)
+            .copied()
+            .unwrap_or_else(|| text.chars().count());
+        let range = starts[offset]..end;
+        let column = wrap::column_at(&text, range.clone(), x).min(row_end(&text, range));
+        (line, column)
+    }
+
+    /// The offset of the visual row containing `column` within its line, and
+    /// the char range of that row.
+    fn row_range(&self, line: &str, column: usize) -> (usize, Range<usize>) {
+        let starts = self.row_starts(line);
+        let row = starts.partition_point(|&start| start <= column) - 1;
+        let end = starts
+            .get(row + 1)
+            .copied()
+            .unwrap_or_else(|| line.chars().count());
+        (row, starts[row]..end)
+    }
+}
+
+/// The last cursor position that is still drawn on the row `range`: rows
+/// other than the last end before the char that starts the next row.
+fn row_end(line: &str, range: Range<usize>) -> usize {
+    if range.end < line.chars().count() {
+        range.end.saturating_sub(1).max(range.start)
+    } else {
+        range.end
+    }
+}
+
+fn count_matches(count: usize) -> String {
+    match count {
+        1 => "1 match".into(),
+        count => format!("{count} matches"),
+    }
+}
+
+/// The gist of a regex error, which is otherwise spread over several lines.
+fn regex_error(error: &regex::Error) -> String {
+    let message = error.to_string();
+    let last_line = message.lines().last().unwrap_or_default();
+    format!(
+        "Invalid pattern: {}",
+        last_line.trim_start_matches("error: ")
+    )
+}
+
+/// Splits `/pattern/replacement/flags`, where any char can stand in for
+/// `/` and is escaped with a backslash. The trailing delimiter is optional.
+fn parse_substitute(command: &str) -> Option<(String, String, String)> {
+    let mut chars = command.chars();
+    let delimiter = chars
+        .next()
+        .filter(|c| !c.is_alphanumeric() && *c != '\\')?;
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut()?.push(next),
+                Some(next) => {
+                    parts.last_mut()?.push(c);
+                    parts.last_mut()?.push(next);
+                }
+                None => parts.last_mut()?.push(c),
+            },
+            _ if c == delimiter => parts.push(String::new()),
+            _ => parts.last_mut()?.push(c),
+        }
+    }
+    let mut parts = parts.into_iter();
+    let pattern = parts.next().filter(|pattern| !pattern.is_empty())?;
+    let template = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    parts.next().is_none().then_some((pattern, template, flags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(text: &str) -> AppState {
+        let buffer = Buffer::from_text(text);
+        AppState {
+            saved_revision: Some(buffer.revision()),
+            buffer,
+            ..Default::default()
+        }
+    }
+
+    fn text(state: &AppState) -> String {
+        let mut text = Vec::new();
+        state.buffer.write_to(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    fn submit(state: &mut AppState, kind: PromptKind, input: &str) {
+        state.submit_prompt(Prompt {
+            kind,
+            input: input.into(),
+        });
+    }
+
+    /// Runs a kill or yank and remembers it as the last action, as the main
+    /// loop does.
+    fn run(state: &mut AppState, action: Action) {
+        match action {
+            Action::KillLine => state.kill_line(),
+            Action::Yank => state.yank(),
+            Action::YankPop => state.yank_pop(),
+            _ => unreachable!(),
+        }
+        state.last_action = Some(action);
+    }
+
+    #[test]
+    fn edits_and_moves_scroll_to_the_cursor() {
+        let mut state = state("a\nb\nc\n");
+        state.viewport_height = 3;
+        state.buffer.move_to(2, 1);
+        state.edit(EditKind::Insert, Buffer::insert_line_break);
+        assert_eq!(state.scroll_position, 1);
+        state.move_cursor(Buffer::move_document_start);
+        assert_eq!(state.scroll_position, 0);
+    }
+
+    #[test]
+    fn edits_that_change_nothing_leave_the_file_unmodified() {
+        let mut state = state("a\n");
+        state.edit(EditKind::Delete, Buffer::backspace);
+        assert!(!state.modified);
+        state.move_cursor(Buffer::move_document_end);
+        state.edit(EditKind::Delete, Buffer::delete);
+        assert!(!state.modified);
+    }
+
+    #[test]
+    fn edits_that_change_nothing_are_not_undone() {
+        let mut state = state("a\n");
+        state.edit(EditKind::Insert, |buffer| buffer.insert_char('b'));
+        state.edit(EditKind::LineBreak, Buffer::insert_line_break);
+        state.undo();
+        state.move_cursor(Buffer::move_document_start);
+        state.edit(EditKind::Delete, Buffer::backspace);
+        state.redo();
+        assert_eq!(text(&state), "b\na\n");
+        state.undo();
+        state.undo();
+        assert_eq!(text(&state), "a\n");
+    }
+
+    #[test]
+    fn up_and_down_move_by_wrapped_rows() {
+        let mut state = state("abcdefgh\nxy\n");
+        state.wrap = true;
+        state.set_viewport(Rect::new(0, 0, 4, 10));
+        state.move_cursor(|buffer| buffer.move_to(0, 1));
+        state.cursor_down();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 0, column: 5 });
+        state.cursor_down();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 1, column: 1 });
+        state.cursor_up();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 0, column: 5 });
+    }
+
+    #[test]
+    fn up_and_down_keep_the_display_column() {
+        let mut state = state("a好b\n好好\nabcd\n");
+        state.move_cursor(|buffer| buffer.move_to(0, 2));
+        state.cursor_down();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 1, column: 1 });
+        state.cursor_down();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 2, column: 2 });
+    }
+
+    #[test]
+    fn undo_and_redo_patch_the_wrap_index() {
+        let mut state = state("one two three\nfour\nfive six seven\n");
+        state.wrap = true;
+        state.set_viewport(Rect::new(0, 0, 5, 10));
+        // The patched index, and one built from scratch.
+        let first_rows = |state: &AppState| {
+            let mut index = WrapIndex::default();
+            index.update(&state.buffer, 5);
+            let lines = 0..state.buffer.line_count();
+            let patched: Vec<_> = lines
+                .clone()
+                .map(|line| state.wrap_index.first_row(line))
+                .collect();
+            let built: Vec<_> = lines.map(|line| index.first_row(line)).collect();
+            (patched, built)
+        };
+        submit(&mut state, PromptKind::Command, "%s/o/ooo ooo/g");
+        let revision = state.buffer.revision();
+        state.undo();
+        let edit = state.buffer.last_edit().unwrap();
+        assert_eq!(edit.base_revision, revision);
+        let (patched, built) = first_rows(&state);
+        assert_eq!(patched, built);
+        state.redo();
+        let (patched, built) = first_rows(&state);
+        assert_eq!(patched, built);
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_text_is_unmodified() {
+        let mut state = state("a\n");
+        state.edit(EditKind::Insert, |buffer| buffer.insert_char('b'));
+        assert!(state.modified);
+        state.undo();
+        assert!(!state.modified);
+        state.redo();
+        assert!(state.modified);
+    }
+
+    #[test]
+    fn normal_mode_keeps_off_the_line_after_the_final_line_break() {
+        let mut state = state("a\nbc\n");
+        state.move_cursor(|buffer| buffer.move_to(1, 2));
+        state.keep_cursor_on_char();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 1, column: 1 });
+        state.move_cursor(Buffer::move_down);
+        state.keep_cursor_on_char();
+        assert_eq!(state.buffer.cursor(), Cursor { line: 1, column: 0 });
+        state.delete_lines(1);
+        assert_eq!(text(&state), "a\n");
+    }
+
+    #[test]
+    fn line_counts_past_the_end_stop_at_the_last_line() {
+        let mut state = state("a\nb\nc\n");
+        state.buffer.move_to(1, 0);
+        state.delete_lines(usize::MAX);
+        assert_eq!(text(&state), "a\n");
+        assert_eq!(state.register.text, "b\nc\n");
+    }
+
+    #[test]
+    fn consecutive_kills_yank_back_together() {
+        let mut state = state("ab\ncd\n");
+        run(&mut state, Action::KillLine);
+        run(&mut state, Action::KillLine);
+        assert_eq!(text(&state), "cd\n");
+        run(&mut state, Action::Yank);
+        assert_eq!(text(&state), "ab\ncd\n");
+        assert_eq!(state.buffer.cursor(), Cursor { line: 1, column: 0 });
+    }
+
+    #[test]
+    fn yank_pop_cycles_through_older_kills() {
+        let mut state = state("one\ntwo\n");
+        run(&mut state, Action::KillLine);
+        state.forget_last_action();
+        state.move_cursor(|buffer| buffer.move_to(1, 0));
+        run(&mut state, Action::KillLine);
+        run(&mut state, Action::Yank);
+        assert_eq!(text(&state), "\ntwo\n");
+        run(&mut state, Action::YankPop);
+        assert_eq!(text(&state), "\none\n");
+        run(&mut state, Action::YankPop);
+        assert_eq!(text(&state), "\ntwo\n");
+        state.undo();
+        assert_eq!(text(&state), "\none\n");
+    }
+
+    #[test]
+    fn yank_pop_needs_a_yank_just_before() {
+        let mut state = state("one\n");
+        run(&mut state, Action::KillLine);
+        run(&mut state, Action::YankPop);
+        assert_eq!(
+            state.message.as_deref(),
+            Some("Previous command was not a yank")
+        );
+        run(&mut state, Action::Yank);
+        state.move_cursor(Buffer::move_left);
+        state.last_action = Some(Action::CursorLeft);
+        state.message = None;
+        run(&mut state, Action::YankPop);
+        assert_eq!(
+            state.message.as_deref(),
+            Some("Previous command was not a yank")
+        );
+        assert_eq!(text(&state), "one\n");
+    }
+
+    #[test]
+    fn parses_substitute_commands() {
+        let parts = |pattern: &str, template: &str, flags: &str| {
+            Some((pattern.into(), template.into(), flags.into()))
+        };
+        assert_eq!(parse_substitute("/a/b/g"), parts("a", "b", "g"));
+        assert_eq!(parse_substitute("/a/b"), parts("a", "b", ""));
+        assert_eq!(parse_substitute("/a"), parts("a", "", ""));
+        assert_eq!(parse_substitute("#a/b#c#"), parts("a/b", "c", ""));
+        assert_eq!(parse_substitute(r"/a\/b/c\d"), parts("a/b", r"c\d", ""));
+        assert_eq!(parse_substitute("//b/"), None);
+        assert_eq!(parse_substitute("/a/b/g/x"), None);
+        assert_eq!(parse_substitute("xa/b/"), None);
+    }
+
+    #[test]
+    fn substitutes_every_match_as_one_undo_step() {
+        let mut state = state("a-b a-b\nb-a\n");
+        submit(&mut state, PromptKind::Command, r"%s/(\w)-(\w)/$2+$1/g");
+        assert_eq!(text(&state), "b+a b+a\na+b\n");
+        state.undo();
+        assert_eq!(text(&state), "a-b a-b\nb-a\n");
+    }
+
+    #[test]
+    fn confirmed_empty_replacements_skip_nothing() {
+        let mut state = state("aaa\n");
+        submit(&mut state, PromptKind::ReplacePattern, "a");
+        submit(&mut state, PromptKind::ReplaceWith, "");
+        for _ in 0..3 {
+            submit(&mut state, PromptKind::ConfirmReplace, "y");
+        }
+        assert_eq!(text(&state), "\n");
+        assert_eq!(state.message.as_deref(), Some("Replaced 3 matches"));
+    }
+}
@@ -0,0 +1,633 @@
+use ropey::{Rope, RopeSlice};
+use std::{
+    borrow::Cow,
+    io,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The lines replaced by an edit: lines `old` of revision `base_revision`
+/// became lines `new` of the following revision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEdit {
+    pub base_revision: u64,
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl LineEdit {
+    /// This edit followed by `next`, which starts from the revision this one
+    /// produced, as one edit covering the lines either of them touched.
+    pub fn then(&self, next: &LineEdit) -> LineEdit {
+        let start = self.old.start.min(next.old.start);
+        // The end of the covered lines, in the revision between the two.
+        let end = self.new.end.max(next.old.end);
+        LineEdit {
+            base_revision: self.base_revision,
+            old: start..self.old.end + (end - self.new.end),
+            new: start..next.new.end + (end - next.old.end),
+        }
+    }
+
+    /// The edit that takes `revision`, which this edit produced, back to
+    /// the text this edit started from.
+    pub fn inverse(&self, revision: u64) -> LineEdit {
+        LineEdit {
+            base_revision: revision,
+            old: self.new.clone(),
+            new: self.old.clone(),
+        }
+    }
+}
+
+/// The text between `anchor` and the cursor. An inclusive selection also
+/// covers the char under the cursor, like Vim's visual mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Cursor,
+    pub inclusive: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Buffer {
+    text: Rope,
+    cursor: Cursor,
+    selection: Option<Selection>,
+    revision: u64,
+    last_edit: Option<LineEdit>,
+    /// The revision that the edits being merged into one started from.
+    group: Option<u64>,
+}
+
+impl Buffer {
+    pub fn from_text(text: &str) -> Self {
+        Self {
+            text: Rope::from_str(text),
+            cursor: Cursor::default(),
+            selection: None,
+            revision: next_revision(),
+            last_edit: None,
+            group: None,
+        }
+    }
+
+    pub fn from_reader<R: io::Read>(reader: R) -> io::Result<Self> {
+        Ok(Self {
+            text: Rope::from_reader(reader)?,
+            cursor: Cursor::default(),
+            selection: None,
+            revision: next_revision(),
+            last_edit: None,
+            group: None,
+        })
+    }
+
+    pub fn write_to<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        self.text.write_to(writer)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+    }
+
+    /// The selected text as an ordered range, `start` included and `end`
+    /// not. An empty selection has no range.
+    pub fn selection_range(&self) -> Option<(Cursor, Cursor)> {
+        let selection = self.selection?;
+        let (start, end) = if selection.anchor <= self.cursor {
+            (selection.anchor, self.cursor)
+        } else {
+            (self.cursor, selection.anchor)
+        };
+        let end = if selection.inclusive {
+            self.position_after(end)
+        } else {
+            end
+        };
+        (start < end).then_some((start, end))
+    }
+
+    /// Identifies the current contents. Every edit assigns a revision that
+    /// has never been used before, so equal revisions mean equal text.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The lines touched by the edit that produced the current revision,
+    /// so that per-line caches can be patched instead of rebuilt.
+    pub fn last_edit(&self) -> Option<&LineEdit> {
+        self.last_edit.as_ref()
+    }
+
+    /// Replaces what `last_edit` reports, for a buffer that was swapped in
+    /// for one it can be reached from by `edit`, as undo does.
+    pub fn set_last_edit(&mut self, edit: Option<LineEdit>) {
+        self.last_edit = edit;
+    }
+
+    /// Runs `f`, recording all the edits it makes as one. Caches then see a
+    /// single `LineEdit` however many steps it took, and can still patch
+    /// instead of rebuilding.
+    pub fn edit_as_one(&mut self, f: impl FnOnce(&mut Self)) {
+        self.group = Some(self.revision);
+        f(self);
+        self.group = None;
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// The number of lines as they are usually counted, where a line break
+    /// at the very end finishes the last line instead of starting another.
+    pub fn lines_in_file(&self) -> usize {
+        let count = self.line_count();
+        if self.line_len(count - 1) == 0 {
+            count - 1
+        } else {
+            count
+        }
+    }
+
+    /// The line ending of the first line, which is taken to be the file's:
+    /// "LF", "CRLF" or "CR". A file without line breaks counts as LF.
+    pub fn line_ending(&self) -> &'static str {
+        let line = self.text.line(0);
+        let len = line.len_chars();
+        match len - trim_line_break(line).len_chars() {
+            2 => "CRLF",
+            1 if line.char(len - 1) == '\r' => "CR",
+            _ => "LF",
+        }
+    }
+
+    /// The chars that end a line in this file, going by `line_ending`.
+    pub fn line_break(&self) -> &'static str {
+        match self.line_ending() {
+            "CRLF" => "\r\n",
+            "CR" => "\r",
+            _ => "\n",
+        }
+    }
+
+    pub fn line(&self, index: usize) -> Cow<'_, str> {
+        trim_line_break(self.text.line(index)).into()
+    }
+
+    /// Iterates over the lines starting at `start`, without their line
+    /// endings. Finding the first line is O(log n) in the document size.
+    pub fn lines_from(&self, start: usize) -> impl Iterator<Item = Cow<'_, str>> {
+        let start = start.min(self.line_count());
+        self.text
+            .lines_at(start)
+            .map(|line| trim_line_break(line).into())
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        if c == '\n' {
+            return self.insert_line_break();
+        }
+        self.insert(self.cursor_char_index(), &c.to_string());
+        self.cursor.column += 1;
+    }
+
+    /// Inserts `text` at the cursor and moves the cursor after it.
+    pub fn insert_text(&mut self, text: &str) {
+        let index = self.cursor_char_index();
+        self.insert(index, text);
+        self.set_cursor_char_index(index + text.chars().count());
+    }
+
+    /// Starts a new line at the cursor, with the file's line ending.
+    pub fn insert_line_break(&mut self) {
+        self.insert(self.cursor_char_index(), self.line_break());
+        self.cursor.line += 1;
+        self.cursor.column = 0;
+    }
+
+    /// Removes the character before the cursor, joining with the previous
+    /// line when the cursor is at the start of a line.
+    pub fn backspace(&mut self) {
+        let end = self.cursor_char_index();
+        if end == 0 {
+            return;
+        }
+        let mut start = end - 1;
+        if start > 0 && self.text.char(start) == '\n' && self.text.char(start - 1) == '\r' {
+            start -= 1;
+        }
+        self.remove(start..end);
+        self.set_cursor_char_index(start);
+    }
+
+    /// Removes the character under the cursor, joining with the next line
+    /// when the cursor is at the end of a line.
+    pub fn delete(&mut self) {
+        let start = self.cursor_char_index();
+        if start >= self.text.len_chars() {
+            return;
+        }
+        let mut end = start + 1;
+        if self.text.char(start) == '\r' && self.text.get_char(end) == Some('\n') {
+            end += 1;
+        }
+        self.remove(start..end);
+    }
+
+    pub fn move_left(&mut self) {
+        if self.cursor.column > 0 {
+            self.cursor.column -= 1;
+        } else if self.cursor.line > 0 {
+            self.cursor.line -= 1;
+            self.cursor.column = self.line_len(self.cursor.line);
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if self.cursor.column < self.line_len(self.cursor.line) {
+            self.cursor.column += 1;
+        } else if self.cursor.line + 1 < self.line_count() {
+            self.cursor.line += 1;
+            self.cursor.column = 0;
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        if self.cursor.line > 0 {
+            self.move_to(self.cursor.line - 1, self.cursor.column);
+        }
+    }
+
+    pub fn move_down(&mut self) {
+        self.move_to(self.cursor.line + 1, self.cursor.column);
+    }
+
+    /// Moves to the start of the next word, like Vim's `w`.
+    pub fn move_word_forward(&mut self) {
+        let mut index = self.cursor_char_index();
+        match self.char_class(index) {
+            Some(CharClass::Blank) | None => (),
+            class => {
+                while self.char_class(index) == class {
+                    index += 1;
+                }
+            }
+        }
+        while self.char_class(index) == Some(CharClass::Blank) {
+            index += 1;
+        }
+        self.set_cursor_char_index(index);
+    }
+
+    /// Moves to the start of the current or previous word, like Vim's `b`.
+    pub fn move_word_backward(&mut self) {
+        let mut index = self.cursor_char_index();
+        while index > 0 && self.char_class(index - 1) == Some(CharClass::Blank) {
+            index -= 1;
+        }
+        if index > 0 {
+            let class = self.char_class(index - 1);
+            while index > 0 && self.char_class(index - 1) == class {
+                index -= 1;
+            }
+        }
+        self.set_cursor_char_index(index);
+    }
+
+    /// Moves to the last char of the current or next word, like Vim's `e`.
+    pub fn move_word_end(&mut self) {
+        let len = self.text.len_chars();
+        let mut index = (self.cursor_char_index() + 1).min(len);
+        while self.char_class(index) == Some(CharClass::Blank) {
+            index += 1;
+        }
+        let class = self.char_class(index);
+        while class.is_some() && self.char_class(index + 1) == class {
+            index += 1;
+        }
+        self.set_cursor_char_index(index.min(len));
+    }
+
+    /// Moves past the end of the next word, like Emacs's `M-f`.
+    pub fn move_forward_word(&mut self) {
+        let mut index = self.cursor_char_index();
+        while self
+            .char_class(index)
+            .is_some_and(|class| class != CharClass::Word)
+        {
+            index += 1;
+        }
+        while self.char_class(index) == Some(CharClass::Word) {
+            index += 1;
+        }
+        self.set_cursor_char_index(index);
+    }
+
+    /// Moves to the start of the previous word, like Emacs's `M-b`.
+    pub fn move_backward_word(&mut self) {
+        let mut index = self.cursor_char_index();
+        while index > 0 && self.char_class(index - 1) != Some(CharClass::Word) {
+            index -= 1;
+        }
+        while index > 0 && self.char_class(index - 1) == Some(CharClass::Word) {
+            index -= 1;
+        }
+        self.set_cursor_char_index(index);
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor.column = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor.column = self.line_len(self.cursor.line);
+    }
+
+    pub fn move_document_start(&mut self) {
+        self.cursor = Cursor::default();
+    }
+
+    pub fn move_document_end(&mut self) {
+        let line = self.line_count() - 1;
+        self.cursor = Cursor {
+            line,
+            column: self.line_len(line),
+        };
+    }
+
+    /// Moves the cursor to the start of `line`, clamped to the last line.
+    pub fn move_to_line(&mut self, line: usize) {
+        self.cursor = Cursor {
+            line: line.min(self.line_count() - 1),
+            column: 0,
+        };
+    }
+
+    /// Moves the cursor to `line` and `column`, clamped to the document.
+    pub fn move_to(&mut self, line: usize, column: usize) {
+        let line = line.min(self.line_count() - 1);
+        self.cursor = Cursor {
+            line,
+            column: column.min(self.line_len(line)),
+        };
+    }
+
+    /// The position one char after `cursor`, which is the start of the
+    /// next line when `cursor` is at the end of its line.
+    pub fn position_after(&self, cursor: Cursor) -> Cursor {
+        if cursor.column < self.line_len(cursor.line) {
+            Cursor {
+                column: cursor.column + 1,
+                ..cursor
+            }
+        } else if cursor.line + 1 < self.line_count() {
+            Cursor {
+                line: cursor.line + 1,
+                column: 0,
+            }
+        } else {
+            cursor
+        }
+    }
+
+    /// The text of `lines`, each ending in a line break.
+    pub fn lines_text(&self, lines: Range<usize>) -> String {
+        let end = lines.end.min(self.line_count());
+        let range = self.text.line_to_char(lines.start)..self.text.line_to_char(end);
+        let mut text = String::from(self.text.slice(range));
+        if !text.ends_with(['\n', '\r']) {
+            text.push_str(self.line_break());
+        }
+        text
+    }
+
+    /// Removes `lines` and moves the cursor to the start of the line that
+    /// took their place. Lines past the last line of the file, like the
+    /// empty one after a final line break, are left alone.
+    pub fn delete_lines(&mut self, lines: Range<usize>) {
+        let end_line = lines.end.min(self.lines_in_file());
+        if end_line <= lines.start {
+            return;
+        }
+        let mut start = self.text.line_to_char(lines.start);
+        let end = self.text.line_to_char(end_line);
+        let last = end_line - 1;
+        let has_line_break = self.text.line(last).len_chars() > self.line_len(last);
+        if !has_line_break && lines.start > 0 {
+            // The last line has no line break of its own to remove, so the
+            // one before it goes instead.
+            let previous = lines.start - 1;
+            start = self.text.line_to_char(previous) + self.line_len(previous);
+        }
+        self.remove(start..end);
+        // A line break at the very end leaves an empty line after it, which
+        // is not one to move to.
+        self.move_to_line(lines.start.min(self.lines_in_file().saturating_sub(1)));
+    }
+
+    /// The text between two positions, `start` included and `end` not.
+    pub fn text_between(&self, start: Cursor, end: Cursor) -> String {
+        self.text
+            .slice(self.char_index(start)..self.char_index(end))
+            .into()
+    }
+
+    /// Removes the text between two positions and moves the cursor to
+    /// `start`.
+    pub fn delete_between(&mut self, start: Cursor, end: Cursor) {
+        let start = self.char_index(start);
+        self.remove(start..self.char_index(end));
+        self.set_cursor_char_index(start);
+    }
+
+    pub fn line_len(&self, line: usize) -> usize {
+        trim_line_break(self.text.line(line)).len_chars()
+    }
+
+    // Edits record the lines from the one holding the char before the edit,
+    // since a '\r' there can merge with or split from a '\n' after it.
+    fn insert(&mut self, index: usize, text: &str) {
+        let first = self.text.char_to_line(index.saturating_sub(1));
+        let old_last = self.text.char_to_line(index);
+        self.text.insert(index, text);
+        let new_last = self.text.char_to_line(index + text.chars().count());
+        self.record_edit(first..old_last + 1, first..new_last + 1);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let first = self.text.char_to_line(range.start.saturating_sub(1));
+        let old_last = self.text.char_to_line(range.end);
+        self.text.remove(range.clone());
+        let new_last = self.text.char_to_line(range.start);
+        self.record_edit(first..old_last + 1, first..new_last + 1);
+    }
+
+    fn record_edit(&mut self, old: Range<usize>, new: Range<usize>) {
+        let edit = LineEdit {
+            base_revision: self.revision,
+            old,
+            new,
+        };
+        self.last_edit = Some(match self.last_edit.take() {
+            Some(last) if self.group == Some(last.base_revision) => last.then(&edit),
+            _ => edit,
+        });
+        self.revision = next_revision();
+        self.selection = None;
+    }
+
+    fn cursor_char_index(&self) -> usize {
+        self.char_index(self.cursor)
+    }
+
+    fn char_index(&self, cursor: Cursor) -> usize {
+        self.text.line_to_char(cursor.line) + cursor.column
+    }
+
+    fn char_class(&self, index: usize) -> Option<CharClass> {
+        self.text.get_char(index).map(CharClass::of)
+    }
+
+    fn set_cursor_char_index(&mut self, index: usize) {
+        let line = self.text.char_to_line(index);
+        self.cursor = Cursor {
+            line,
+            column: index - self.text.line_to_char(line),
+        };
+    }
+}
+
+fn trim_line_break(line: RopeSlice) -> RopeSlice {
+    let mut end = line.len_chars();
+    if end > 0 && line.char(end - 1) == '\n' {
+        end -= 1;
+    }
+    if end > 0 && line.char(end - 1) == '\r' {
+        end -= 1;
+    }
+    line.slice(..end)
+}
+
+/// What Vim-style word motions consider a word: a run of word chars, or a
+/// run of other non-blank chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            Self::Blank
+        } else if c.is_alphanumeric() || c == '_' {
+            Self::Word
+        } else {
+            Self::Punctuation
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(buffer: &Buffer) -> String {
+        let mut text = Vec::new();
+        buffer.write_to(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn line_breaks_match_the_file() {
+        for (text, expected) in [
+            ("a\nb\n", "a\n\nb\n"),
+            ("a\r\nb\r\n", "a\r\n\r\nb\r\n"),
+            ("a\rb\r", "a\r\rb\r"),
+        ] {
+            let mut buffer = Buffer::from_text(text);
+            buffer.move_end();
+            buffer.insert_line_break();
+            assert_eq!(self::text(&buffer), expected);
+            assert_eq!(buffer.cursor(), Cursor { line: 1, column: 0 });
+        }
+    }
+
+    #[test]
+    fn line_text_ends_in_the_file_line_break() {
+        let buffer = Buffer::from_text("a\r\nb");
+        assert_eq!(buffer.lines_text(0..2), "a\r\nb\r\n");
+    }
+
+    #[test]
+    fn edits_made_as_one_merge_their_line_ranges() {
+        let mut buffer = Buffer::from_text("a\nb\nc\nd\n");
+        let base_revision = buffer.revision();
+        buffer.edit_as_one(|buffer| {
+            buffer.move_to(3, 0);
+            buffer.insert_text("x\ny\n");
+            buffer.move_to(1, 1);
+            buffer.delete();
+        });
+        assert_eq!(text(&buffer), "a\nbc\nx\ny\nd\n");
+        assert_eq!(
+            buffer.last_edit(),
+            Some(&LineEdit {
+                base_revision,
+                old: 1..4,
+                new: 1..5,
+            })
+        );
+    }
+
+    #[test]
+    fn deleting_the_last_line_keeps_the_final_line_break() {
+        let mut buffer = Buffer::from_text("a\nb\n");
+        buffer.delete_lines(1..2);
+        assert_eq!(text(&buffer), "a\n");
+        assert_eq!(buffer.cursor(), Cursor { line: 0, column: 0 });
+    }
+
+    #[test]
+    fn deleting_a_last_line_without_a_line_break_takes_the_one_before() {
+        let mut buffer = Buffer::from_text("a\nb");
+        buffer.delete_lines(1..2);
+        assert_eq!(text(&buffer), "a");
+    }
+
+    #[test]
+    fn deleting_the_line_after_the_final_line_break_does_nothing() {
+        let mut buffer = Buffer::from_text("a\nb\n");
+        buffer.delete_lines(2..3);
+        assert_eq!(text(&buffer), "a\nb\n");
+    }
+
+    #[test]
+    fn deleting_lines_in_the_middle() {
+        let mut buffer = Buffer::from_text("a\nb\nc\n");
+        buffer.delete_lines(0..2);
+        assert_eq!(text(&buffer), "c\n");
+        assert_eq!(buffer.cursor(), Cursor { line: 0, column: 0 });
+    }
+}
@@ -0,0 +1,101 @@
+use crate::buffer::Buffer;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Loads `path` into a buffer. A missing file gives an empty buffer so that
+/// new files can be created by saving.
+pub fn load(path: &Path) -> io::Result<Buffer> {
+    match File::open(path) {
+        Ok(file) => Buffer::from_reader(BufReader::new(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Buffer::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the buffer to a temporary file next to `path` and renames it into
+/// place, so a failed save never leaves a truncated file behind. A symlink
+/// is followed, so that the file it points to is replaced and not the link.
+pub fn save(path: &Path, buffer: &Buffer) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_owned(),
+        Err(e) => return Err(e),
+    };
+    let path = path.as_path();
+    let temp_path = temp_path(path);
+    let result = write_file(&temp_path, path, buffer).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+    result
+}
+
+fn write_file(temp_path: &Path, path: &Path, buffer: &Buffer) -> io::Result<()> {
+    let file = File::create(temp_path)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    let mut writer = BufWriter::new(file);
+    buffer.write_to(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "ratatui-type-and-scroll-{name}-{}",
+            std::process::id()
+        ));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn text(buffer: &Buffer) -> String {
+        let mut text = Vec::new();
+        buffer.write_to(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads_the_same_text() {
+        let dir = temp_dir("round-trip");
+        let path = dir.join("file.txt");
+        assert_eq!(text(&load(&path).unwrap()), "");
+        let buffer = Buffer::from_text("one\r\ntwo\r\n");
+        save(&path, &buffer).unwrap();
+        assert_eq!(text(&load(&path).unwrap()), "one\r\ntwo\r\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saving_through_a_symlink_keeps_the_link() {
+        let dir = temp_dir("symlink");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        save(&link, &Buffer::from_text("new\n")).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,84 @@
+use crate::buffer::{Buffer, LineEdit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Insert,
+    LineBreak,
+    Delete,
+    Paste,
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub buffer: Buffer,
+    pub scroll_position: usize,
+}
+
+/// A state to go back to, and the lines that changed between it and the
+/// state after it, so that undo and redo can patch caches.
+type Step = (Snapshot, Option<LineEdit>);
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    open_group: bool,
+    /// Whether a batch is open, and if so whether it has recorded an edit.
+    batch: Option<bool>,
+}
+
+impl History {
+    /// Records the state from before an edit, and the lines the edit
+    /// changed. Consecutive insertions share the snapshot taken before the
+    /// first of them, so they undo together.
+    pub fn record(&mut self, kind: EditKind, before: Snapshot, edit: Option<LineEdit>) {
+        let coalesce = self.batch == Some(true) || (kind == EditKind::Insert && self.open_group);
+        match self.undo.last_mut() {
+            Some((_, last_edit)) if coalesce => {
+                *last_edit = last_edit
+                    .take()
+                    .zip(edit)
+                    .map(|(last_edit, edit)| last_edit.then(&edit));
+            }
+            _ => self.undo.push((before, edit)),
+        }
+        if let Some(recorded) = &mut self.batch {
+            *recorded = true;
+        }
+        self.redo.clear();
+        self.open_group = kind == EditKind::Insert;
+    }
+
+    pub fn close_group(&mut self) {
+        self.open_group = false;
+    }
+
+    /// Makes all edits until `end_batch` undo as one.
+    pub fn begin_batch(&mut self) {
+        self.open_group = false;
+        self.batch = Some(false);
+    }
+
+    pub fn end_batch(&mut self) {
+        self.batch = None;
+        self.open_group = false;
+    }
+
+    /// Goes back a step from `current`. Returns the state to restore, and
+    /// the lines that change on the way there.
+    pub fn undo(&mut self, current: Snapshot) -> Option<(Snapshot, Option<LineEdit>)> {
+        let (previous, edit) = self.undo.pop()?;
+        let revision = current.buffer.revision();
+        self.redo.push((current, edit.clone()));
+        self.open_group = false;
+        Some((previous, edit.map(|edit| edit.inverse(revision))))
+    }
+
+    /// Goes forward a step from `current`, like `undo`.
+    pub fn redo(&mut self, current: Snapshot) -> Option<(Snapshot, Option<LineEdit>)> {
+        let (next, edit) = self.redo.pop()?;
+        self.undo.push((current, edit.clone()));
+        self.open_group = false;
+        Some((next, edit))
+    }
+}
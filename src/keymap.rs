@@ -0,0 +1,671 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Insert(char),
+    LineBreak,
+    Backspace,
+    Delete,
+    CursorLeft,
+    CursorRight,
+    CursorHome,
+    CursorEnd,
+    CursorUp,
+    CursorDown,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    ForwardWord,
+    BackwardWord,
+    LineStart,
+    LineEnd,
+    /// Moves right without leaving the line, for Vim's `a`.
+    Append,
+    /// Deletes the char under the cursor, or before it, without joining
+    /// lines, for Vim's `x` and `X`.
+    DeleteInLine,
+    BackspaceInLine,
+    JumpToLine(usize),
+    DocumentStart,
+    DocumentEnd,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+    PageUp,
+    PageDown,
+    DeleteLines(usize),
+    YankLines(usize),
+    Paste,
+    PasteBefore,
+    OpenLineBelow,
+    OpenLineAbove,
+    StartSelection,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectHome,
+    SelectEnd,
+    DeleteSelection,
+    YankSelection,
+    Copy,
+    Cut,
+    PasteClipboard,
+    KillLine,
+    Yank,
+    YankPop,
+    Undo,
+    Redo,
+    Save,
+    GoToLine,
+    CommandPrompt,
+    Search,
+    SearchNext,
+    SearchPrevious,
+    Replace,
+    ToggleCase,
+    ToggleWholeWord,
+    ToggleWrap,
+    ToggleLineNumbers,
+    ToggleRelativeNumbers,
+    Cancel,
+    Suspend,
+    Quit,
+}
+
+impl Action {
+    /// Whether the action changes the text, which Vim's Normal mode only
+    /// does through its own commands.
+    pub fn edits(self) -> bool {
+        matches!(
+            self,
+            Action::Insert(_)
+                | Action::LineBreak
+                | Action::Backspace
+                | Action::Delete
+                | Action::DeleteInLine
+                | Action::BackspaceInLine
+                | Action::DeleteLines(_)
+                | Action::Paste
+                | Action::PasteBefore
+                | Action::OpenLineBelow
+                | Action::OpenLineAbove
+                | Action::DeleteSelection
+                | Action::Cut
+                | Action::PasteClipboard
+                | Action::KillLine
+                | Action::Yank
+                | Action::YankPop
+        )
+    }
+}
+
+/// The actions that can be named in the keymap file. The README lists them
+/// in this order, which a test checks.
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("line_break", Action::LineBreak),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("cursor_left", Action::CursorLeft),
+    ("cursor_right", Action::CursorRight),
+    ("cursor_home", Action::CursorHome),
+    ("cursor_end", Action::CursorEnd),
+    ("cursor_up", Action::CursorUp),
+    ("cursor_down", Action::CursorDown),
+    ("word_forward", Action::WordForward),
+    ("word_backward", Action::WordBackward),
+    ("word_end", Action::WordEnd),
+    ("forward_word", Action::ForwardWord),
+    ("backward_word", Action::BackwardWord),
+    ("line_start", Action::LineStart),
+    ("line_end", Action::LineEnd),
+    ("document_start", Action::DocumentStart),
+    ("document_end", Action::DocumentEnd),
+    ("scroll_up", Action::ScrollUp),
+    ("scroll_down", Action::ScrollDown),
+    ("scroll_left", Action::ScrollLeft),
+    ("scroll_right", Action::ScrollRight),
+    ("page_up", Action::PageUp),
+    ("page_down", Action::PageDown),
+    ("delete_line", Action::DeleteLines(1)),
+    ("yank_line", Action::YankLines(1)),
+    ("paste", Action::Paste),
+    ("paste_before", Action::PasteBefore),
+    ("open_line_below", Action::OpenLineBelow),
+    ("open_line_above", Action::OpenLineAbove),
+    ("select_left", Action::SelectLeft),
+    ("select_right", Action::SelectRight),
+    ("select_up", Action::SelectUp),
+    ("select_down", Action::SelectDown),
+    ("select_home", Action::SelectHome),
+    ("select_end", Action::SelectEnd),
+    ("delete_selection", Action::DeleteSelection),
+    ("yank_selection", Action::YankSelection),
+    ("copy", Action::Copy),
+    ("cut", Action::Cut),
+    ("paste_clipboard", Action::PasteClipboard),
+    ("kill_line", Action::KillLine),
+    ("yank", Action::Yank),
+    ("yank_pop", Action::YankPop),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("save", Action::Save),
+    ("go_to_line", Action::GoToLine),
+    ("search", Action::Search),
+    ("search_next", Action::SearchNext),
+    ("search_previous", Action::SearchPrevious),
+    ("replace", Action::Replace),
+    ("toggle_case", Action::ToggleCase),
+    ("toggle_whole_word", Action::ToggleWholeWord),
+    ("toggle_wrap", Action::ToggleWrap),
+    ("toggle_line_numbers", Action::ToggleLineNumbers),
+    ("toggle_relative_numbers", Action::ToggleRelativeNumbers),
+    ("cancel", Action::Cancel),
+    ("suspend", Action::Suspend),
+    ("quit", Action::Quit),
+];
+
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    // Ctrl+Z suspends, as in other terminal programs, so undo is on Alt+U
+    // as in nano.
+    ("ctrl+z", Action::Suspend),
+    ("alt+u", Action::Undo),
+    ("ctrl+y", Action::Redo),
+    ("ctrl+s", Action::Save),
+    ("ctrl+g", Action::GoToLine),
+    ("ctrl+f", Action::Search),
+    ("f3", Action::SearchNext),
+    ("shift+f3", Action::SearchPrevious),
+    ("ctrl+r", Action::Replace),
+    ("alt+c", Action::ToggleCase),
+    ("alt+w", Action::ToggleWholeWord),
+    ("ctrl+q", Action::Quit),
+    ("ctrl+c", Action::Copy),
+    ("ctrl+x", Action::Cut),
+    ("ctrl+v", Action::PasteClipboard),
+    ("alt+z", Action::ToggleWrap),
+    ("alt+n", Action::ToggleLineNumbers),
+    ("alt+r", Action::ToggleRelativeNumbers),
+    ("alt+left", Action::ScrollLeft),
+    ("alt+right", Action::ScrollRight),
+    ("ctrl+home", Action::DocumentStart),
+    ("ctrl+end", Action::DocumentEnd),
+    ("up", Action::ScrollUp),
+    ("down", Action::ScrollDown),
+    ("enter", Action::LineBreak),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("left", Action::CursorLeft),
+    ("right", Action::CursorRight),
+    ("home", Action::CursorHome),
+    ("end", Action::CursorEnd),
+    ("shift+left", Action::SelectLeft),
+    ("shift+right", Action::SelectRight),
+    ("shift+up", Action::SelectUp),
+    ("shift+down", Action::SelectDown),
+    ("shift+home", Action::SelectHome),
+    ("shift+end", Action::SelectEnd),
+    ("pageup", Action::PageUp),
+    ("pagedown", Action::PageDown),
+    ("esc", Action::Cancel),
+];
+
+/// What the Emacs preset changes about the defaults.
+const EMACS_BINDINGS: &[(&str, Action)] = &[
+    ("ctrl+a", Action::LineStart),
+    ("ctrl+e", Action::LineEnd),
+    ("ctrl+f", Action::CursorRight),
+    ("ctrl+b", Action::CursorLeft),
+    ("ctrl+n", Action::CursorDown),
+    ("ctrl+p", Action::CursorUp),
+    ("ctrl+d", Action::Delete),
+    ("ctrl+k", Action::KillLine),
+    ("ctrl+y", Action::Yank),
+    ("alt+y", Action::YankPop),
+    ("alt+f", Action::ForwardWord),
+    ("alt+b", Action::BackwardWord),
+    ("ctrl+v", Action::PageDown),
+    ("alt+v", Action::PageUp),
+    ("alt+<", Action::DocumentStart),
+    ("alt+>", Action::DocumentEnd),
+    ("ctrl+g", Action::Cancel),
+    ("ctrl+s", Action::Search),
+    ("ctrl+r", Action::SearchPrevious),
+    ("alt+%", Action::Replace),
+    ("ctrl+x u", Action::Undo),
+    ("ctrl+x ctrl+s", Action::Save),
+    ("ctrl+x ctrl+c", Action::Quit),
+];
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        ACTION_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, action)| action)
+            .ok_or_else(|| format!("unknown action `{name}`"))
+    }
+}
+
+/// One key together with the modifiers held while pressing it. Shift is
+/// part of the char for char keys, so `shift+a` and `A` are the same chord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let mut modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self { code, modifiers }
+    }
+
+    fn without_shift(self) -> Self {
+        Self::new(self.code, self.modifiers - KeyModifiers::SHIFT)
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(key: KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("tab", KeyCode::Tab),
+    ("space", KeyCode::Char(' ')),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    /// Parses chords like `ctrl+s`, `alt+left`, `f3` or `G`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = text;
+        while let Some((modifier, rest)) = key.split_once('+') {
+            if rest.is_empty() {
+                break;
+            }
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier `{modifier}`")),
+            };
+            key = rest;
+        }
+        let lower = key.to_ascii_lowercase();
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_uppercase().next().unwrap_or(c))
+            }
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match KEY_NAMES.iter().find(|(name, _)| *name == lower) {
+                Some(&(_, code)) => code,
+                None => match lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key `{key}`")),
+                },
+            },
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "ctrl+"),
+            (KeyModifiers::ALT, "alt+"),
+            (KeyModifiers::SHIFT, "shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                f.write_str(name)?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::F(n) => write!(f, "f{n}"),
+            code => match KEY_NAMES.iter().find(|&&(_, c)| c == code) {
+                Some((name, _)) => f.write_str(name),
+                None => write!(f, "{code:?}"),
+            },
+        }
+    }
+}
+
+fn parse_sequence(text: &str) -> Result<Vec<KeyChord>, String> {
+    let sequence = text
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<KeyChord>, _>>()?;
+    if sequence.is_empty() {
+        return Err("no keys given".into());
+    }
+    Ok(sequence)
+}
+
+fn format_sequence(sequence: &[KeyChord]) -> String {
+    let chords: Vec<_> = sequence.iter().map(KeyChord::to_string).collect();
+    chords.join(" ")
+}
+
+/// The editing style keys are handled in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Default,
+    Vim,
+    Emacs,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    preset: Preset,
+    #[serde(default)]
+    bindings: BTreeMap<String, String>,
+}
+
+/// Maps key chords, and sequences of them, to actions. Plain chars that are
+/// not bound to anything are inserted.
+#[derive(Debug)]
+pub struct Keymap {
+    preset: Preset,
+    bindings: HashMap<Vec<KeyChord>, Action>,
+    prefixes: HashSet<Vec<KeyChord>>,
+    pending: Vec<KeyChord>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(Preset::Default, default_bindings(Preset::Default))
+    }
+}
+
+impl Keymap {
+    fn new(preset: Preset, bindings: HashMap<Vec<KeyChord>, Action>) -> Self {
+        let prefixes = bindings
+            .keys()
+            .flat_map(|sequence| (1..sequence.len()).map(|len| sequence[..len].to_vec()))
+            .collect();
+        Self {
+            preset,
+            bindings,
+            prefixes,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn preset(&self) -> Preset {
+        self.preset
+    }
+
+    /// Loads the keymap file if there is one. Problems with it are all
+    /// reported at once, so they can be fixed in one go.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => anyhow::bail!("Could not read {}: {e}", path.display()),
+        };
+        Self::from_toml(&text).map_err(|errors| {
+            anyhow::anyhow!(
+                "Invalid key bindings in {}:\n  {}",
+                path.display(),
+                errors.join("\n  ")
+            )
+        })
+    }
+
+    /// Builds a keymap from the `[bindings]` table of a keymap file, which
+    /// adds to and overrides the defaults. Binding a key to `"none"` removes
+    /// its default binding.
+    pub fn from_toml(text: &str) -> Result<Self, Vec<String>> {
+        let config: Config = toml::from_str(text).map_err(|e| vec![e.to_string()])?;
+        let mut bindings = default_bindings(config.preset);
+        let mut errors = Vec::new();
+        for (keys, name) in &config.bindings {
+            let sequence = match parse_sequence(keys) {
+                Ok(sequence) => sequence,
+                Err(e) => {
+                    errors.push(format!("`{keys}`: {e}"));
+                    continue;
+                }
+            };
+            if name == "none" {
+                bindings.remove(&sequence);
+                continue;
+            }
+            match name.parse() {
+                Ok(action) => {
+                    bindings.insert(sequence, action);
+                }
+                Err(e) => errors.push(format!("`{keys}`: {e}")),
+            }
+        }
+        for sequence in bindings.keys() {
+            for len in 1..sequence.len() {
+                if bindings.contains_key(&sequence[..len]) {
+                    errors.push(format!(
+                        "`{}` can never be typed because `{}` is bound too",
+                        format_sequence(sequence),
+                        format_sequence(&sequence[..len])
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(Self::new(config.preset, bindings))
+        } else {
+            errors.sort();
+            Err(errors)
+        }
+    }
+
+    /// Feeds a key press to the keymap. Returns `None` while a sequence is
+    /// still incomplete, and when the keys turn out not to be bound.
+    pub fn resolve(&mut self, key: KeyEvent) -> Option<Action> {
+        let chord = KeyChord::from(key);
+        self.pending.push(chord);
+        // Shift only matters where it is bound, so that shift+left still
+        // moves the cursor like left does.
+        for candidate in [chord, chord.without_shift()] {
+            *self.pending.last_mut()? = candidate;
+            if let Some(&action) = self.bindings.get(&self.pending) {
+                self.pending.clear();
+                return Some(action);
+            }
+            if self.prefixes.contains(&self.pending) {
+                return None;
+            }
+        }
+        let sequence = std::mem::take(&mut self.pending);
+        match chord.code {
+            KeyCode::Char(c) if sequence.len() == 1 && chord.modifiers.is_empty() => {
+                Some(Action::Insert(c))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn default_bindings(preset: Preset) -> HashMap<Vec<KeyChord>, Action> {
+    let preset_bindings = match preset {
+        Preset::Emacs => EMACS_BINDINGS,
+        Preset::Default | Preset::Vim => &[],
+    };
+    let parse = |&(keys, action): &(&str, Action)| {
+        let sequence = parse_sequence(keys).expect("default key bindings are valid");
+        (sequence, action)
+    };
+    let mut bindings: HashMap<_, _> = DEFAULT_BINDINGS.iter().map(parse).collect();
+    for (sequence, action) in preset_bindings.iter().map(parse) {
+        // A preset's sequences take over keys the defaults bind on their
+        // own, like Emacs's `ctrl+x` prefix.
+        for len in 1..sequence.len() {
+            bindings.remove(&sequence[..len]);
+        }
+        bindings.insert(sequence, action);
+    }
+    bindings
+}
+
+/// `keys.toml` in the user's config directory, following the XDG base
+/// directory spec.
+pub fn config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("ratatui-type-and-scroll").join("keys.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(text: &str) -> KeyChord {
+        text.parse().unwrap()
+    }
+
+    fn press(keymap: &mut Keymap, text: &str) -> Option<Action> {
+        let chord = chord(text);
+        keymap.resolve(KeyEvent::new(chord.code, chord.modifiers))
+    }
+
+    #[test]
+    fn parses_chords() {
+        assert_eq!(
+            chord("ctrl+s"),
+            KeyChord::new(KeyCode::Char('s'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            chord("Alt+Left"),
+            KeyChord::new(KeyCode::Left, KeyModifiers::ALT)
+        );
+        assert_eq!(
+            chord("f3"),
+            KeyChord::new(KeyCode::F(3), KeyModifiers::NONE)
+        );
+        assert_eq!(chord("shift+a"), chord("A"));
+        assert_eq!(
+            chord("ctrl++"),
+            KeyChord::new(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_modifiers() {
+        assert!("hyper+x".parse::<KeyChord>().is_err());
+        assert!("f13".parse::<KeyChord>().is_err());
+        assert!("ctrl+nope".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn chords_display_as_they_parse() {
+        for text in ["ctrl+s", "alt+left", "shift+f3", "space", "G"] {
+            assert_eq!(chord(text).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn bindings_add_to_and_remove_defaults() {
+        let mut keymap = Keymap::from_toml(
+            r#"
+            [bindings]
+            "f2" = "go_to_line"
+            "ctrl+s" = "none"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(press(&mut keymap, "f2"), Some(Action::GoToLine));
+        assert_eq!(press(&mut keymap, "ctrl+s"), None);
+        assert_eq!(press(&mut keymap, "ctrl+q"), Some(Action::Quit));
+    }
+
+    #[test]
+    fn reports_every_invalid_binding() {
+        let errors = Keymap::from_toml(
+            r#"
+            [bindings]
+            "f2" = "fly"
+            "hyper+x" = "save"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn reports_sequences_hidden_by_their_prefix() {
+        let errors = Keymap::from_toml(
+            r#"
+            [bindings]
+            "ctrl+k" = "save"
+            "ctrl+k ctrl+s" = "save"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            ["`ctrl+k ctrl+s` can never be typed because `ctrl+k` is bound too"]
+        );
+    }
+
+    #[test]
+    fn readme_lists_every_action() {
+        let readme = include_str!("../README.md");
+        let start = readme.find("The actions are").unwrap();
+        let end = readme[start..]
+            .find("\n\n")
+            .map_or(readme.len(), |end| start + end);
+        let listed: Vec<_> = readme[start..end].split('`').skip(1).step_by(2).collect();
+        let names: Vec<_> = ACTION_NAMES.iter().map(|&(name, _)| name).collect();
+        assert_eq!(listed, names);
+    }
+
+    #[test]
+    fn resolves_sequences() {
+        let mut keymap = Keymap::from_toml("preset = \"emacs\"").unwrap();
+        assert_eq!(press(&mut keymap, "ctrl+x"), None);
+        assert_eq!(press(&mut keymap, "ctrl+s"), Some(Action::Save));
+        assert_eq!(press(&mut keymap, "x"), Some(Action::Insert('x')));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::buffer::Cursor;
+
+/// Killed text, most recent last, like Emacs's kill ring.
+#[derive(Debug, Default)]
+pub struct KillRing {
+    kills: Vec<String>,
+    /// Where the last yank started and ended and how far back in the ring
+    /// its text was, so that `yank_pop` can replace it with an older kill.
+    pub last_yank: Option<(Cursor, Cursor, usize)>,
+}
+
+impl KillRing {
+    const CAPACITY: usize = 60;
+
+    /// Adds killed text to the ring. Consecutive kills are appended to one
+    /// entry, so they can be yanked back together.
+    pub fn kill(&mut self, text: String, append: bool) {
+        match self.kills.last_mut() {
+            Some(last) if append => last.push_str(&text),
+            _ => {
+                if self.kills.len() == Self::CAPACITY {
+                    self.kills.remove(0);
+                }
+                self.kills.push(text);
+            }
+        }
+    }
+
+    /// The kill `back` entries before the most recent one, wrapping around.
+    pub fn get(&self, back: usize) -> Option<&str> {
+        let len = self.kills.len();
+        if len == 0 {
+            return None;
+        }
+        Some(&self.kills[len - 1 - back % len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appending_kills_join_the_last_entry() {
+        let mut ring = KillRing::default();
+        ring.kill("one".into(), false);
+        ring.kill(" two".into(), true);
+        ring.kill("three".into(), false);
+        assert_eq!(ring.get(0), Some("three"));
+        assert_eq!(ring.get(1), Some("one two"));
+    }
+
+    #[test]
+    fn going_back_wraps_around() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.get(0), None);
+        ring.kill("one".into(), false);
+        ring.kill("two".into(), false);
+        assert_eq!(ring.get(2), Some("two"));
+        assert_eq!(ring.get(3), Some("one"));
+    }
+
+    #[test]
+    fn old_kills_fall_off_the_end() {
+        let mut ring = KillRing::default();
+        for i in 0..=KillRing::CAPACITY {
+            ring.kill(i.to_string(), false);
+        }
+        assert_eq!(ring.get(KillRing::CAPACITY - 1), Some("1"));
+        assert_eq!(ring.get(KillRing::CAPACITY), Some("60"));
+    }
+}
@@ -0,0 +1,15 @@
+pub mod app;
+pub mod buffer;
+pub mod clipboard;
+pub mod file;
+pub mod history;
+pub mod keymap;
+pub mod kill_ring;
+pub mod prompt;
+pub mod recovery;
+pub mod search;
+pub mod syntax;
+pub mod terminal;
+pub mod ui;
+pub mod vim;
+pub mod wrap;
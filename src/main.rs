@@ -1,13 +1,25 @@
-use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
-};
 use ratatui::{
-    prelude::{CrosstermBackend, Frame, Terminal},
-    text::Line,
-    widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarState},
+    layout::{Position, Rect},
+    prelude::{CrosstermBackend, Terminal},
+};
+use ratatui_type_and_scroll::{
+    app::AppState,
+    buffer::Buffer,
+    file,
+    history::EditKind,
+    keymap::{Action, Keymap, Preset},
+    prompt::{Prompt, PromptKind},
+    recovery,
+    syntax::{Highlighter, Language},
+    terminal,
+    ui::{layout, ui},
+    vim::{Mode, Vim},
+};
+use std::{
+    io::{stdout, Write},
+    path::PathBuf,
+    process::ExitCode,
 };
-use std::io::stdout;
 use tokio::sync::{broadcast, mpsc};
 use tokio_stream::StreamExt;
 
@@ -15,97 +27,510 @@ use tokio_stream::StreamExt;
 struct Shutdown;
 
 enum Event {
-    Key(char),
-    ScrollDown,
-    ScrollUp,
-    LineBreak,
-    Exit,
-}
-
-#[derive(Debug, Default)]
-struct AppState {
-    scroll_state: ScrollbarState,
-    scroll_position: usize,
-    line_count: usize,
-    text: String,
+    Key(crossterm::event::KeyEvent),
+    Action(Action),
+    Mouse(crossterm::event::MouseEvent),
+    /// Text pasted into the terminal, which arrives in one piece rather
+    /// than as key presses.
+    Paste(String),
+    Resize(u16, u16),
+    Redraw,
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    enable_raw_mode()?;
-    stdout().execute(EnterAlternateScreen)?;
+async fn main() -> anyhow::Result<ExitCode> {
+    let path = std::env::args_os().nth(1).map(PathBuf::from);
+    let buffer = match &path {
+        Some(path) => file::load(path)?,
+        None => Buffer::default(),
+    };
+    let keymap = Keymap::load()?;
+    let highlighter = path
+        .as_deref()
+        .and_then(Language::from_path)
+        .map(Highlighter::new);
+    let state = AppState {
+        saved_revision: Some(buffer.revision()),
+        buffer,
+        path,
+        highlighter,
+        vim: (keymap.preset() == Preset::Vim).then(Vim::default),
+        line_numbers: true,
+        ..Default::default()
+    };
+
+    install_panic_hook();
+    terminal::enter()?;
     let (event_sender, event_receiver) = mpsc::channel(16);
     let (shutdown_sender, shutdown_receiver) = broadcast::channel(1);
     let poll_task = tokio::spawn(poll_keys(event_sender, shutdown_receiver));
-    let draw_task = tokio::spawn(draw_loop(event_receiver, shutdown_sender));
+    let draw_task = tokio::spawn(draw_loop(state, keymap, event_receiver, shutdown_sender));
 
-    let polling_result = poll_task.await?;
-    let drawing_result = draw_task.await?;
+    let polling_result = poll_task.await.map_err(Into::into).and_then(|x| x);
+    let drawing_result = draw_task.await.map_err(Into::into).and_then(|x| x);
 
-    disable_raw_mode()?;
-    stdout().execute(LeaveAlternateScreen)?;
+    let restored = terminal::leave();
 
-    if let Err(e) = polling_result {
-        println!("Polling error: {e:?}");
+    // After a hangup there is no terminal to write to, so the unsaved text
+    // is saved first and writing errors is allowed to fail.
+    let failed = polling_result.is_err() || drawing_result.is_err();
+    if failed {
+        report_recovery();
     }
-    if let Err(e) = drawing_result {
-        println!("Drawing error: {e:?}");
+    let mut stderr = std::io::stderr();
+    if let Err(e) = &polling_result {
+        writeln!(stderr, "Polling error: {e:?}").ok();
+    }
+    if let Err(e) = &drawing_result {
+        writeln!(stderr, "Drawing error: {e:?}").ok();
     }
 
-    Ok(())
+    if failed {
+        return Ok(ExitCode::FAILURE);
+    }
+    restored?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Gives the terminal back to the shell before the default hook prints the
+/// panic message, and saves whatever the user had not saved yet.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        terminal::leave().ok();
+        report_recovery();
+        default_hook(info);
+    }));
+}
+
+fn report_recovery() {
+    let mut stderr = std::io::stderr();
+    match recovery::dump() {
+        Some(Ok(path)) => writeln!(stderr, "Unsaved changes were written to {}", path.display()),
+        Some(Err(e)) => writeln!(stderr, "Unsaved changes could not be recovered: {e}"),
+        None => Ok(()),
+    }
+    .ok();
 }
 
 async fn draw_loop(
+    mut state: AppState,
+    mut keymap: Keymap,
     mut stream: mpsc::Receiver<Event>,
     shutdown: broadcast::Sender<Shutdown>,
 ) -> anyhow::Result<()> {
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     let mut shutdown_receiver = shutdown.subscribe();
-    let refresh_time = std::time::Duration::from_millis(100);
-    let mut state = AppState::default();
-    state.text.push_str("Hello, World!\n");
-    state.line_count = 1;
+    let mut signals = Signals::new()?;
+    state.dirty = true;
 
     loop {
+        if state.dirty {
+            terminal.draw(|frame| ui(frame, &mut state))?;
+            state.dirty = false;
+        }
         let maybe_event = tokio::select! {
-            x = stream.recv() => x,
+            x = stream.recv() => match x {
+                Some(event) => Some(event),
+                None => break,
+            },
             _ = shutdown_receiver.recv() => break,
-            _ = tokio::time::sleep(refresh_time) => None,
+            _ = timer(state.message_expiry) => None,
+            signal = signals.recv() => match signal {
+                Signal::Stop => Some(Event::Action(Action::Suspend)),
+                Signal::Continue => Some(Event::Redraw),
+                Signal::Terminate | Signal::Hangup => {
+                    shutdown.send(Shutdown).ok();
+                    anyhow::bail!("Received {}", signal.name());
+                }
+            },
         };
-        match maybe_event {
-            Some(Event::Exit) => {
-                shutdown.send(Shutdown).ok();
-                break;
+        let actions = match maybe_event {
+            Some(Event::Key(key)) => match &mut state.vim {
+                Some(vim) if state.prompt.is_none() => {
+                    let mode = vim.mode();
+                    let actions = vim.handle(key, &mut keymap);
+                    // Entering Insert mode changes the status line by itself.
+                    state.dirty |= vim.mode() != mode;
+                    actions
+                }
+                _ => keymap.resolve(key).into_iter().collect(),
+            },
+            Some(Event::Action(action)) => vec![action],
+            Some(Event::Mouse(mouse)) => {
+                let view = |state: &AppState| {
+                    (
+                        state.scroll_position,
+                        state.horizontal_scroll,
+                        state.buffer.cursor(),
+                        state.buffer.selection(),
+                    )
+                };
+                let before = view(&state);
+                mouse_event(&mut state, mouse);
+                state.dirty |= view(&state) != before;
+                Vec::new()
             }
-            Some(Event::Key(c)) => {
-                state.text.push(c);
+            Some(Event::Paste(text)) => {
+                paste(&mut state, &text);
+                state.dirty = true;
+                Vec::new()
             }
-            Some(Event::LineBreak) => {
-                state.text.push('\n');
-                state.line_count += 1;
+            Some(Event::Resize(width, height)) => {
+                let areas = layout(Rect::new(0, 0, width, height), state.gutter_width());
+                state.resize(areas.text, areas.scrollbar);
+                state.dirty = true;
+                Vec::new()
             }
-            Some(Event::ScrollDown) => {
-                state.scroll_state.next();
-                state.scroll_position = state
-                    .scroll_position
-                    .saturating_add(1)
-                    .clamp(0, state.line_count);
+            Some(Event::Redraw) => {
+                terminal.clear()?;
+                state.dirty = true;
+                Vec::new()
             }
-            Some(Event::ScrollUp) => {
-                state.scroll_state.prev();
-                state.scroll_position = state
-                    .scroll_position
-                    .saturating_sub(1)
-                    .clamp(0, state.line_count);
+            None => {
+                let had_message = state.message.is_some();
+                state.expire_message();
+                state.dirty |= state.message.is_some() != had_message;
+                Vec::new()
+            }
+        };
+        state.dirty |= !actions.is_empty();
+        // Actions that come from one command, like Vim's `3x`, undo as one.
+        let batch = actions.len() > 1;
+        if batch {
+            state.history.begin_batch();
+        }
+        for action in actions {
+            match action {
+                Action::Quit => state.request_quit(),
+                Action::Suspend => {
+                    terminal::suspend()?;
+                    terminal.clear()?;
+                }
+                action if state.prompt.is_some() => prompt_action(&mut state, action),
+                action => {
+                    run_action(&mut state, action);
+                    state.last_action = Some(action);
+                }
             }
-            None => (),
         }
-        terminal.draw(|frame| ui(frame, &mut state))?;
+        if batch {
+            state.history.end_batch();
+        }
+        let normal_mode = state
+            .vim
+            .as_ref()
+            .is_some_and(|vim| vim.mode() == Mode::Normal);
+        if normal_mode && state.prompt.is_none() {
+            state.keep_cursor_on_char();
+        }
+        recovery::track(&state);
+        if state.quit {
+            shutdown.send(Shutdown).ok();
+            break;
+        }
     }
 
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Terminate,
+    Hangup,
+    Stop,
+    Continue,
+}
+
+impl Signal {
+    fn name(self) -> &'static str {
+        match self {
+            Signal::Terminate => "SIGTERM",
+            Signal::Hangup => "SIGHUP",
+            Signal::Stop => "SIGTSTP",
+            Signal::Continue => "SIGCONT",
+        }
+    }
+}
+
+/// The signals the app reacts to, which only exist on Unix.
+struct Signals {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    stop: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    continued: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    #[cfg(unix)]
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+            stop: signal(SignalKind::from_raw(libc::SIGTSTP))?,
+            continued: signal(SignalKind::from_raw(libc::SIGCONT))?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    #[cfg(unix)]
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = self.hangup.recv() => Signal::Hangup,
+            _ = self.stop.recv() => Signal::Stop,
+            _ = self.continued.recv() => Signal::Continue,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) -> Signal {
+        std::future::pending().await
+    }
+}
+
+/// Sleeps until `deadline`, or forever when there is none.
+async fn timer(deadline: Option<std::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn run_action(state: &mut AppState, action: Action) {
+    match action {
+        Action::Insert(c) => state.edit_selection(EditKind::Insert, |b| b.insert_char(c)),
+        Action::Backspace => state.edit_selection(EditKind::Delete, Buffer::backspace),
+        Action::Delete => state.edit_selection(EditKind::Delete, Buffer::delete),
+        Action::CursorLeft => state.move_cursor(Buffer::move_left),
+        Action::CursorRight => state.move_cursor(Buffer::move_right),
+        Action::CursorHome => state.cursor_home(),
+        Action::CursorEnd => state.cursor_end(),
+        Action::CursorUp => state.cursor_up(),
+        Action::CursorDown => state.cursor_down(),
+        Action::WordForward => state.move_cursor(Buffer::move_word_forward),
+        Action::WordBackward => state.move_cursor(Buffer::move_word_backward),
+        Action::WordEnd => state.move_cursor(Buffer::move_word_end),
+        Action::ForwardWord => state.move_cursor(Buffer::move_forward_word),
+        Action::BackwardWord => state.move_cursor(Buffer::move_backward_word),
+        Action::LineStart => state.move_cursor(Buffer::move_home),
+        Action::LineEnd => state.move_cursor(Buffer::move_end),
+        Action::Append => {
+            let cursor = state.buffer.cursor();
+            if cursor.column < state.buffer.line_len(cursor.line) {
+                state.move_cursor(Buffer::move_right);
+            }
+        }
+        Action::DeleteInLine => {
+            let cursor = state.buffer.cursor();
+            if cursor.column < state.buffer.line_len(cursor.line) {
+                state.edit(EditKind::Delete, Buffer::delete);
+            }
+        }
+        Action::BackspaceInLine => {
+            if state.buffer.cursor().column > 0 {
+                state.edit(EditKind::Delete, Buffer::backspace);
+            }
+        }
+        Action::JumpToLine(line) => state.move_cursor(|b| b.move_to_line(line.saturating_sub(1))),
+        Action::Undo => state.undo(),
+        Action::Redo => state.redo(),
+        Action::Save => state.save(),
+        Action::LineBreak => state.edit_selection(EditKind::LineBreak, Buffer::insert_line_break),
+        Action::ScrollDown => state.scroll_down(1),
+        Action::ScrollUp => state.scroll_up(1),
+        Action::ScrollLeft => state.scroll_left(1),
+        Action::ScrollRight => state.scroll_right(1),
+        Action::PageDown => state.scroll_down(state.viewport_height.max(1)),
+        Action::PageUp => state.scroll_up(state.viewport_height.max(1)),
+        Action::DocumentStart => state.move_cursor(Buffer::move_document_start),
+        Action::DocumentEnd => state.move_cursor(Buffer::move_document_end),
+        Action::DeleteLines(count) => state.delete_lines(count),
+        Action::YankLines(count) => state.yank_lines(count),
+        Action::Paste => state.paste(false),
+        Action::PasteBefore => state.paste(true),
+        Action::OpenLineBelow => {
+            state.edit(EditKind::LineBreak, |b| {
+                b.move_end();
+                b.insert_line_break();
+            });
+        }
+        Action::OpenLineAbove => {
+            state.edit(EditKind::LineBreak, |b| {
+                b.move_home();
+                b.insert_line_break();
+                b.move_up();
+            });
+        }
+        Action::StartSelection => state.start_selection(true),
+        Action::SelectLeft => state.extend_selection(|s| s.move_cursor(Buffer::move_left)),
+        Action::SelectRight => state.extend_selection(|s| s.move_cursor(Buffer::move_right)),
+        Action::SelectUp => state.extend_selection(AppState::cursor_up),
+        Action::SelectDown => state.extend_selection(AppState::cursor_down),
+        Action::SelectHome => state.extend_selection(AppState::cursor_home),
+        Action::SelectEnd => state.extend_selection(AppState::cursor_end),
+        Action::DeleteSelection => state.delete_selection(),
+        Action::YankSelection => state.yank_selection(),
+        Action::Copy => state.copy(),
+        Action::Cut => state.cut(),
+        Action::PasteClipboard => state.paste_clipboard(),
+        Action::KillLine => state.kill_line(),
+        Action::Yank => state.yank(),
+        Action::YankPop => state.yank_pop(),
+        Action::GoToLine => state.prompt = Some(Prompt::new(PromptKind::GoToLine)),
+        Action::CommandPrompt => state.prompt = Some(Prompt::new(PromptKind::Command)),
+        Action::Search => state.start_search(),
+        Action::SearchNext => state.search_next(false),
+        Action::SearchPrevious => state.search_next(true),
+        Action::Replace => state.start_replace(),
+        Action::ToggleCase => state.toggle_search_option(|o| o.ignore_case = !o.ignore_case),
+        Action::ToggleWholeWord => state.toggle_search_option(|o| o.whole_word = !o.whole_word),
+        Action::ToggleWrap => state.toggle_wrap(),
+        Action::ToggleLineNumbers => state.toggle_line_numbers(),
+        Action::ToggleRelativeNumbers => state.toggle_relative_numbers(),
+        Action::Cancel => {
+            state.buffer.set_selection(None);
+            state.search = None;
+        }
+        Action::Suspend | Action::Quit => (),
+    }
+}
+
+/// Inserts pasted text as one edit, with the file's line endings. Prompts
+/// only take a single line.
+fn paste(state: &mut AppState, text: &str) {
+    // Terminals send pasted line breaks as carriage returns.
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    if text.is_empty() {
+        return;
+    }
+    state.forget_last_action();
+    match &mut state.prompt {
+        Some(prompt) => {
+            prompt
+                .input
+                .push_str(text.lines().next().unwrap_or_default());
+            state.preview_search();
+        }
+        None => state.paste_text(&text.replace('\n', state.buffer.line_break())),
+    }
+}
+
+fn prompt_action(state: &mut AppState, action: Action) {
+    state.forget_last_action();
+    let Some(prompt) = &mut state.prompt else {
+        return;
+    };
+    match action {
+        Action::Insert(c) if prompt.is_question() => {
+            prompt.input.push(c);
+            if let Some(prompt) = state.prompt.take() {
+                state.submit_prompt(prompt);
+            }
+        }
+        Action::Insert(c) => {
+            prompt.input.push(c);
+            state.preview_search();
+        }
+        Action::Backspace => {
+            prompt.input.pop();
+            state.preview_search();
+        }
+        Action::LineBreak => {
+            if let Some(prompt) = state.prompt.take() {
+                state.submit_prompt(prompt);
+            }
+        }
+        // Searching again while typing the query cycles through matches.
+        Action::Search | Action::SearchNext if prompt.kind == PromptKind::Search => {
+            state.search_next(false);
+        }
+        Action::SearchPrevious if prompt.kind == PromptKind::Search => state.search_next(true),
+        Action::ToggleCase => state.toggle_search_option(|o| o.ignore_case = !o.ignore_case),
+        Action::ToggleWholeWord => state.toggle_search_option(|o| o.whole_word = !o.whole_word),
+        Action::Cancel => state.cancel_prompt(),
+        _ => (),
+    }
+}
+
+fn mouse_event(state: &mut AppState, mouse: crossterm::event::MouseEvent) {
+    const WHEEL_STEP: usize = 3;
+
+    let position = Position::new(mouse.column, mouse.row);
+    let scrollbar = state.scrollbar_area;
+    let shift = mouse
+        .modifiers
+        .contains(crossterm::event::KeyModifiers::SHIFT);
+    if mouse.kind != crossterm::event::MouseEventKind::Moved {
+        state.forget_last_action();
+    }
+    match mouse.kind {
+        crossterm::event::MouseEventKind::ScrollDown if shift => state.scroll_right(WHEEL_STEP),
+        crossterm::event::MouseEventKind::ScrollUp if shift => state.scroll_left(WHEEL_STEP),
+        crossterm::event::MouseEventKind::ScrollDown => state.scroll_down(WHEEL_STEP),
+        crossterm::event::MouseEventKind::ScrollUp => state.scroll_up(WHEEL_STEP),
+        crossterm::event::MouseEventKind::ScrollRight => state.scroll_right(WHEEL_STEP),
+        crossterm::event::MouseEventKind::ScrollLeft => state.scroll_left(WHEEL_STEP),
+        crossterm::event::MouseEventKind::Down(crossterm::event::MouseButton::Left)
+            if scrollbar.contains(position) =>
+        {
+            if mouse.row == scrollbar.top() {
+                state.scroll_up(1);
+            } else if mouse.row + 1 == scrollbar.bottom() {
+                state.scroll_down(1);
+            } else {
+                state.dragging_scrollbar = true;
+                state.scroll_to_track_row(mouse.row);
+            }
+        }
+        crossterm::event::MouseEventKind::Down(crossterm::event::MouseButton::Left) => {
+            state.click_text(position);
+            state.dragging_selection = state.text_area.contains(position);
+        }
+        crossterm::event::MouseEventKind::Drag(crossterm::event::MouseButton::Left)
+            if state.dragging_scrollbar =>
+        {
+            state.scroll_to_track_row(mouse.row);
+        }
+        crossterm::event::MouseEventKind::Drag(crossterm::event::MouseButton::Left)
+            if state.dragging_selection =>
+        {
+            let area = state.text_area;
+            // Dragging past the top or bottom keeps selecting as it scrolls.
+            if mouse.row < area.top() {
+                state.scroll_up(1);
+            } else if mouse.row >= area.bottom() {
+                state.scroll_down(1);
+            }
+            let position = Position::new(
+                mouse
+                    .column
+                    .min(area.right().saturating_sub(1))
+                    .max(area.left()),
+                mouse
+                    .row
+                    .min(area.bottom().saturating_sub(1))
+                    .max(area.top()),
+            );
+            state.extend_selection(|state| state.click_text(position));
+        }
+        crossterm::event::MouseEventKind::Up(crossterm::event::MouseButton::Left) => {
+            state.dragging_scrollbar = false;
+            state.dragging_selection = false;
+        }
+        _ => (),
+    }
+}
+
 async fn poll_keys(
     sender: mpsc::Sender<Event>,
     mut shutdown: broadcast::Receiver<Shutdown>,
@@ -117,42 +542,27 @@ async fn poll_keys(
             _ = shutdown.recv() => break,
         };
         if let Some(x) = maybe_event {
-            if let crossterm::event::Event::Key(key) = x? {
-                let event = if key.kind == crossterm::event::KeyEventKind::Press {
-                    match key.code {
-                        crossterm::event::KeyCode::Char('q') => Some(Event::Exit),
-                        crossterm::event::KeyCode::Char(c) => Some(Event::Key(c)),
-                        crossterm::event::KeyCode::Up => Some(Event::ScrollUp),
-                        crossterm::event::KeyCode::Down => Some(Event::ScrollDown),
-                        crossterm::event::KeyCode::Enter => Some(Event::LineBreak),
-                        _ => None,
-                    }
-                } else {
-                    None
-                };
-                if let Some(e) = event {
-                    sender.send(e).await?;
+            let event = match x? {
+                crossterm::event::Event::Key(key)
+                    if key.kind == crossterm::event::KeyEventKind::Press =>
+                {
+                    Some(Event::Key(key))
+                }
+                crossterm::event::Event::Mouse(mouse)
+                    if mouse.kind != crossterm::event::MouseEventKind::Moved =>
+                {
+                    Some(Event::Mouse(mouse))
+                }
+                crossterm::event::Event::Paste(text) => Some(Event::Paste(text)),
+                crossterm::event::Event::Resize(width, height) => {
+                    Some(Event::Resize(width, height))
                 }
+                _ => None,
+            };
+            if let Some(e) = event {
+                sender.send(e).await?;
             }
         }
     }
     Ok(())
 }
-
-fn ui(frame: &mut Frame, state: &mut AppState) {
-    state.scroll_state = state.scroll_state.content_length(state.line_count);
-
-    let render_lines: Vec<Line> = state
-        .text
-        .lines()
-        .skip(state.scroll_position)
-        .map(Into::into)
-        .collect();
-
-    frame.render_widget(
-        Paragraph::new(render_lines)
-            .block(Block::default().title("Greeting").borders(Borders::ALL)),
-        frame.area(),
-    );
-    frame.render_stateful_widget(Scrollbar::default(), frame.area(), &mut state.scroll_state);
-}
@@ -0,0 +1,45 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    GoToLine,
+    ConfirmQuit,
+    Command,
+    Search,
+    ReplacePattern,
+    ReplaceWith,
+    ConfirmReplace,
+}
+
+#[derive(Debug)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind) -> Self {
+        Self {
+            kind,
+            input: String::new(),
+        }
+    }
+
+    /// Whether the prompt takes a single key instead of a line of input.
+    pub fn is_question(&self) -> bool {
+        matches!(
+            self.kind,
+            PromptKind::ConfirmQuit | PromptKind::ConfirmReplace
+        )
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self.kind {
+            PromptKind::GoToLine => "Go to line: ",
+            PromptKind::ConfirmQuit => "Unsaved changes. Quit anyway? (y/n) ",
+            PromptKind::Command => ":",
+            PromptKind::Search => "Search: ",
+            PromptKind::ReplacePattern => "Replace regex: ",
+            PromptKind::ReplaceWith => "Replace with: ",
+            PromptKind::ConfirmReplace => "Replace this match? (y/n/a/q) ",
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{app::AppState, buffer::Buffer, file};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+struct Unsaved {
+    buffer: Buffer,
+    path: Option<PathBuf>,
+}
+
+static UNSAVED: Mutex<Option<Unsaved>> = Mutex::new(None);
+
+/// Keeps a copy of any unsaved changes where a panic hook can reach them.
+/// The copy shares its text with the buffer, so this is cheap to call after
+/// every event.
+pub fn track(state: &AppState) {
+    let unsaved = state.modified.then(|| Unsaved {
+        buffer: state.buffer.clone(),
+        path: state.path.clone(),
+    });
+    if let Ok(mut tracked) = UNSAVED.lock() {
+        *tracked = unsaved;
+    }
+}
+
+/// Writes the tracked unsaved changes to a recovery file and returns its
+/// path. Only the first call writes anything.
+pub fn dump() -> Option<io::Result<PathBuf>> {
+    let unsaved = UNSAVED.try_lock().ok()?.take()?;
+    let path = recovery_path(unsaved.path.as_deref());
+    Some(file::save(&path, &unsaved.buffer).map(|()| path))
+}
+
+fn recovery_path(path: Option<&Path>) -> PathBuf {
+    match path {
+        Some(path) => {
+            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(".recovery");
+            path.with_file_name(file_name)
+        }
+        None => std::env::temp_dir().join(format!(
+            "ratatui-type-and-scroll-{}.recovery",
+            std::process::id()
+        )),
+    }
+}
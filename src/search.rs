@@ -0,0 +1,314 @@
+use crate::buffer::{Buffer, Cursor, LineEdit};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// How queries match, which can be toggled while searching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// Compiles `query`, which is taken literally unless `regex` is set.
+    pub fn build(self, query: &str, regex: bool) -> Result<Regex, regex::Error> {
+        let pattern = if regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        let pattern = if self.whole_word {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+    }
+
+    /// Describes the options for the status line.
+    pub fn describe(self) -> String {
+        let case = if self.ignore_case {
+            "Ignoring case"
+        } else {
+            "Matching case"
+        };
+        if self.whole_word {
+            format!("{case}, whole words only")
+        } else {
+            case.into()
+        }
+    }
+}
+
+/// A find-and-replace in progress, going through the matches of the
+/// current search.
+#[derive(Debug)]
+pub struct Replacement {
+    pub template: String,
+    pub count: usize,
+}
+
+/// The matches of a search query, kept up to date with the buffer.
+#[derive(Debug)]
+pub struct Search {
+    pub query: String,
+    regex: Regex,
+    matches: Vec<(Cursor, Cursor)>,
+    revision: u64,
+    current: Option<usize>,
+}
+
+impl Search {
+    pub fn new(query: &str, regex: Regex, buffer: &Buffer) -> Self {
+        let mut search = Self {
+            query: query.into(),
+            regex,
+            matches: Vec::new(),
+            revision: 0,
+            current: None,
+        };
+        search.refresh(buffer);
+        search
+    }
+
+    /// Searches for a query that extends this search's, which can only
+    /// match on lines this one matched on. Only works for literal queries
+    /// with the same options, and not for whole words.
+    pub fn narrow(self, query: &str, regex: Regex, buffer: &Buffer) -> Self {
+        let mut lines: Vec<usize> = self.matches.iter().map(|(start, _)| start.line).collect();
+        lines.dedup();
+        let mut matches = Vec::new();
+        let mut texts = buffer.lines_from(0);
+        let mut next = 0;
+        for line in lines {
+            // Nearby lines are walked to, and far ones looked up.
+            if line - next > 64 {
+                texts = buffer.lines_from(line);
+                next = line;
+            }
+            if let Some(text) = texts.nth(line - next) {
+                find_in_line(&regex, line, &text, &mut matches);
+            }
+            next = line + 1;
+        }
+        Self {
+            query: query.into(),
+            regex,
+            matches,
+            revision: buffer.revision(),
+            current: None,
+        }
+    }
+
+    /// Brings the matches up to date with the buffer, searching only the
+    /// lines the last edit touched if this search was up to date before it.
+    pub fn update(&mut self, buffer: &Buffer) {
+        if self.revision == buffer.revision() {
+            return;
+        }
+        match buffer.last_edit() {
+            Some(edit) if edit.base_revision == self.revision => self.patch(buffer, edit),
+            _ => self.refresh(buffer),
+        }
+    }
+
+    /// The matches on `lines`, found without looking at the rest of the
+    /// document.
+    pub fn matches_in(&self, buffer: &Buffer, lines: Range<usize>) -> Vec<(Cursor, Cursor)> {
+        let mut matches = Vec::new();
+        if !self.query.is_empty() {
+            for (line, text) in lines.clone().zip(buffer.lines_from(lines.start)) {
+                find_in_line(&self.regex, line, &text, &mut matches);
+            }
+        }
+        matches
+    }
+
+    /// The start and end of each match, in document order.
+    pub fn matches(&self) -> &[(Cursor, Cursor)] {
+        &self.matches
+    }
+
+    pub fn current(&self) -> Option<(Cursor, Cursor)> {
+        self.current.map(|index| self.matches[index])
+    }
+
+    /// Makes the first match at or after `cursor` current, wrapping around
+    /// to the start of the document.
+    pub fn select_at(&mut self, cursor: Cursor) -> Option<Cursor> {
+        let index = self.matches.partition_point(|&(start, _)| start < cursor);
+        self.select(self.wrap_forward(index))
+    }
+
+    /// Makes the first match at or after `cursor` current, without wrapping
+    /// around.
+    pub fn select_from(&mut self, cursor: Cursor) -> Option<Cursor> {
+        let index = self.matches.partition_point(|&(start, _)| start < cursor);
+        self.select((index < self.matches.len()).then_some(index))
+    }
+
+    /// Makes the match after `cursor`, or before it when going backwards,
+    /// current. Searches wrap around the ends of the document.
+    pub fn select_next(&mut self, cursor: Cursor, backwards: bool) -> Option<Cursor> {
+        let index = if backwards {
+            let before = self.matches.partition_point(|&(start, _)| start < cursor);
+            before.checked_sub(1).or(self.matches.len().checked_sub(1))
+        } else {
+            let after = self.matches.partition_point(|&(start, _)| start <= cursor);
+            self.wrap_forward(after)
+        };
+        self.select(index)
+    }
+
+    /// What the match at `start` is replaced with: `template` with `$1` or
+    /// `${name}` filled in from the match's capture groups.
+    pub fn replacement(&self, buffer: &Buffer, start: Cursor, template: &str) -> String {
+        let text = buffer.line(start.line);
+        let byte = text
+            .char_indices()
+            .nth(start.column)
+            .map_or(text.len(), |(i, _)| i);
+        let mut replacement = String::new();
+        if let Some(captures) = self.regex.captures_at(&text, byte) {
+            captures.expand(template, &mut replacement);
+        }
+        replacement
+    }
+
+    /// Describes where the current match is, like "match 3 of 17".
+    pub fn status(&self) -> String {
+        match self.current {
+            Some(index) => format!("match {} of {}", index + 1, self.matches.len()),
+            None => format!("No matches for {}", self.query),
+        }
+    }
+
+    /// `index`, or the first match when `index` is past the last one.
+    fn wrap_forward(&self, index: usize) -> Option<usize> {
+        if index < self.matches.len() {
+            Some(index)
+        } else {
+            (!self.matches.is_empty()).then_some(0)
+        }
+    }
+
+    fn select(&mut self, index: Option<usize>) -> Option<Cursor> {
+        self.current = index;
+        self.current().map(|(start, _)| start)
+    }
+
+    fn refresh(&mut self, buffer: &Buffer) {
+        let current = self.current();
+        self.matches = if self.query.is_empty() {
+            Vec::new()
+        } else {
+            self.matches_in(buffer, 0..buffer.line_count())
+        };
+        self.revision = buffer.revision();
+        self.reselect(current);
+    }
+
+    /// Replaces the matches on the lines an edit replaced, and moves the
+    /// ones below it to their new lines.
+    fn patch(&mut self, buffer: &Buffer, edit: &LineEdit) {
+        let current = self.current();
+        let from = self
+            .matches
+            .partition_point(|(start, _)| start.line < edit.old.start);
+        let to = self
+            .matches
+            .partition_point(|(start, _)| start.line < edit.old.end);
+        let found = self.matches_in(buffer, edit.new.clone());
+        let after = from + found.len();
+        self.matches.splice(from..to, found);
+        for (start, end) in &mut self.matches[after..] {
+            start.line = start.line - edit.old.end + edit.new.end;
+            end.line = end.line - edit.old.end + edit.new.end;
+        }
+        self.revision = buffer.revision();
+        self.reselect(current);
+    }
+
+    /// Makes the first match at or after where `current` started current.
+    fn reselect(&mut self, current: Option<(Cursor, Cursor)>) {
+        self.current = current.and_then(|(start, _)| {
+            self.matches
+                .iter()
+                .position(|&(match_start, _)| match_start >= start)
+        });
+    }
+}
+
+/// Adds the matches of `regex` on one line to `matches`.
+fn find_in_line(regex: &Regex, line: usize, text: &str, matches: &mut Vec<(Cursor, Cursor)>) {
+    let mut column = 0;
+    let mut counted = 0;
+    for found in regex.find_iter(text) {
+        column += text[counted..found.start()].chars().count();
+        counted = found.start();
+        let len = found.as_str().chars().count();
+        matches.push((
+            Cursor { line, column },
+            Cursor {
+                line,
+                column: column + len,
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, buffer: &Buffer) -> Search {
+        let regex = SearchOptions::default().build(query, false).unwrap();
+        Search::new(query, regex, buffer)
+    }
+
+    #[test]
+    fn edits_patch_matches() {
+        let mut buffer = Buffer::from_text("ab\nx ab\nab ab\n");
+        let mut patched = search("ab", &buffer);
+        buffer.move_to(1, 0);
+        buffer.insert_text("ab\n\n");
+        patched.update(&buffer);
+        assert_eq!(patched.matches(), search("ab", &buffer).matches());
+        buffer.move_to(3, 2);
+        buffer.backspace();
+        buffer.move_to(0, 1);
+        patched.update(&buffer);
+        assert_eq!(patched.matches(), search("ab", &buffer).matches());
+    }
+
+    #[test]
+    fn narrowing_matches_a_new_search() {
+        let buffer = Buffer::from_text("aab\naa\nb aab aa\n");
+        let regex = SearchOptions::default().build("aab", false).unwrap();
+        let narrowed = search("aa", &buffer).narrow("aab", regex, &buffer);
+        assert_eq!(narrowed.matches(), search("aab", &buffer).matches());
+    }
+
+    #[test]
+    fn replacements_fill_in_capture_groups() {
+        let buffer = Buffer::from_text("key = value\nx=1\n");
+        let regex = SearchOptions::default()
+            .build(r"(?<k>\w+)\s*=\s*(\w+)", true)
+            .unwrap();
+        let search = Search::new("", regex, &buffer);
+        let start = Cursor { line: 1, column: 0 };
+        assert_eq!(search.replacement(&buffer, start, "$2: ${k}"), "1: x");
+    }
+
+    #[test]
+    fn matches_in_only_searches_given_lines() {
+        let buffer = Buffer::from_text("ab\nab\nab\n");
+        let matches = search("b", &buffer).matches_in(&buffer, 1..2);
+        assert_eq!(
+            matches,
+            [(Cursor { line: 1, column: 1 }, Cursor { line: 1, column: 2 })]
+        );
+    }
+}
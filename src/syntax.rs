@@ -0,0 +1,841 @@
+use crate::buffer::{Buffer, Cursor, LineEdit};
+use std::{ops::Range, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Toml,
+    Json,
+    Markdown,
+    Shell,
+}
+
+impl Language {
+    /// Picks the language from a file's name or extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.file_name()?.to_str()? {
+            "Cargo.lock" => return Some(Self::Toml),
+            ".bashrc" | ".bash_profile" | ".profile" | ".zshrc" => return Some(Self::Shell),
+            _ => (),
+        }
+        match path.extension()?.to_str()? {
+            "rs" => Some(Self::Rust),
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            "md" | "markdown" => Some(Self::Markdown),
+            "sh" | "bash" | "zsh" => Some(Self::Shell),
+            _ => None,
+        }
+    }
+
+    /// Splits one line into tokens, starting in `state`, and returns the
+    /// state the next line starts in.
+    fn highlight_line(self, state: State, line: &str) -> (Vec<Token>, State) {
+        let mut lexer = Lexer::new(line);
+        let state = match self {
+            Language::Rust => rust(&mut lexer, state),
+            Language::Toml => toml(&mut lexer, state),
+            Language::Json => json(&mut lexer),
+            Language::Markdown => markdown(&mut lexer, state),
+            Language::Shell => shell(&mut lexer, state),
+        };
+        (lexer.tokens, state)
+    }
+}
+
+/// What a token is, which decides how it is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Keyword,
+    Type,
+    Function,
+    Macro,
+    Attribute,
+    String,
+    Number,
+    Constant,
+    Comment,
+    Variable,
+    Key,
+    Heading,
+    Marker,
+    Emphasis,
+    Strong,
+    Code,
+    Link,
+}
+
+type Token = (Range<usize>, Kind);
+
+/// What a line starts inside of, for constructs that span lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Normal,
+    /// A Rust block comment, nested this deep.
+    BlockComment(u32),
+    /// A string opened with this quote, or TOML's triple of it.
+    String(char),
+    /// A Rust raw string with this many `#`s.
+    RawString(u32),
+    /// A Markdown code block fenced with this many of this char.
+    Fence(char, usize),
+}
+
+/// Highlights the visible lines of a buffer. The state each line starts in
+/// is cached, so that only lines that were edited, or scrolled to for the
+/// first time, need looking at.
+#[derive(Debug)]
+pub struct Highlighter {
+    language: Language,
+    revision: Option<u64>,
+    /// The state at the start of each line. The first `valid` are known to
+    /// be right.
+    starts: Vec<State>,
+    valid: usize,
+    /// Lines below the last edit whose start states were right before it.
+    /// They are still right once the lines above them end in the same
+    /// state as before.
+    reusable: Option<Range<usize>>,
+}
+
+impl Highlighter {
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            revision: None,
+            starts: vec![State::Normal],
+            valid: 1,
+            reusable: None,
+        }
+    }
+
+    /// The tokens of `lines`, as ranges of the buffer.
+    pub fn highlight(
+        &mut self,
+        buffer: &Buffer,
+        lines: Range<usize>,
+    ) -> Vec<(Cursor, Cursor, Kind)> {
+        self.update(buffer);
+        self.ensure(buffer, lines.start);
+        let mut state = self.starts[lines.start];
+        let mut tokens = Vec::new();
+        for (line, text) in lines.clone().zip(buffer.lines_from(lines.start)) {
+            let (line_tokens, end) = self.language.highlight_line(state, &text);
+            tokens.extend(line_tokens.into_iter().map(|(range, kind)| {
+                (
+                    Cursor {
+                        line,
+                        column: range.start,
+                    },
+                    Cursor {
+                        line,
+                        column: range.end,
+                    },
+                    kind,
+                )
+            }));
+            state = end;
+        }
+        tokens
+    }
+
+    fn update(&mut self, buffer: &Buffer) {
+        if self.revision == Some(buffer.revision()) {
+            return;
+        }
+        match buffer.last_edit() {
+            Some(edit) if self.revision == Some(edit.base_revision) => self.patch(edit),
+            _ => {
+                self.starts.truncate(1);
+                self.valid = 1;
+                self.reusable = None;
+            }
+        }
+        self.revision = Some(buffer.revision());
+    }
+
+    /// Forgets the start states an edit may have changed, keeping the ones
+    /// below it around in case they turn out not to have.
+    fn patch(&mut self, edit: &LineEdit) {
+        let valid = self.valid;
+        // The line the edit starts on still starts the way it did.
+        self.valid = valid.min(edit.old.start + 1);
+        if valid > edit.old.end {
+            let replaced = vec![State::Normal; edit.new.len() - 1];
+            self.starts
+                .splice(edit.old.start + 1..edit.old.end, replaced);
+            self.reusable = Some(edit.new.end..valid - edit.old.end + edit.new.end);
+        } else {
+            self.starts.truncate(self.valid);
+            self.reusable = None;
+        }
+    }
+
+    /// Works out the start states up to line `last`.
+    fn ensure(&mut self, buffer: &Buffer, last: usize) {
+        while self.valid <= last {
+            let first = self.valid - 1;
+            for (line, text) in (first..last).zip(buffer.lines_from(first)) {
+                let (_, end) = self.language.highlight_line(self.starts[line], &text);
+                let next = line + 1;
+                if let Some(reusable) = self.reusable.clone() {
+                    if reusable.contains(&next) && self.starts[next] == end {
+                        self.valid = reusable.end;
+                        self.reusable = None;
+                        break;
+                    }
+                }
+                if next < self.starts.len() {
+                    self.starts[next] = end;
+                } else {
+                    self.starts.push(end);
+                }
+                self.valid = next + 1;
+            }
+        }
+    }
+}
+
+/// Walks over the chars of a line, collecting tokens.
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    tokens: Vec<Token>,
+}
+
+impl Lexer {
+    fn new(line: &str) -> Self {
+        Self {
+            chars: line.chars().collect(),
+            pos: 0,
+            tokens: Vec::new(),
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn previous(&self) -> Option<char> {
+        self.pos.checked_sub(1).map(|i| self.chars[i])
+    }
+
+    fn at(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    /// Where `text` next occurs at or after `from`.
+    fn find(&self, text: &str, from: usize) -> Option<usize> {
+        let pattern: Vec<char> = text.chars().collect();
+        (from..self.chars.len()).find(|&i| self.chars[i..].starts_with(&pattern))
+    }
+
+    fn emit(&mut self, start: usize, kind: Kind) {
+        if self.pos > start {
+            self.tokens.push((start..self.pos, kind));
+        }
+    }
+
+    fn rest(&mut self, start: usize, kind: Kind) {
+        self.pos = self.chars.len();
+        self.emit(start, kind);
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        self.take_while(is_word_char);
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn number(&mut self) {
+        while let Some(c) = self.peek() {
+            let decimal_point = c == '.' && self.peek_at(1).is_some_and(|c| c.is_ascii_digit());
+            if c.is_ascii_alphanumeric() || c == '_' || decimal_point {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Moves past `end`, skipping backslash escapes if `escapes` is set.
+    /// Returns whether `end` was found before the end of the line.
+    fn until(&mut self, end: &str, escapes: bool) -> bool {
+        while !self.done() {
+            if escapes && self.peek() == Some('\\') {
+                self.pos = (self.pos + 2).min(self.chars.len());
+            } else if self.at(end) {
+                self.pos += end.chars().count();
+                return true;
+            } else {
+                self.pos += 1;
+            }
+        }
+        false
+    }
+
+    /// Finishes a string that started at `start`, returning `open` if the
+    /// line ends before it does.
+    fn string(&mut self, start: usize, end: &str, escapes: bool, open: State) -> State {
+        let closed = self.until(end, escapes);
+        self.emit(start, Kind::String);
+        if closed {
+            State::Normal
+        } else {
+            open
+        }
+    }
+
+    /// Finishes a Rust block comment, which can nest.
+    fn block_comment(&mut self, start: usize, mut depth: u32) -> State {
+        while !self.done() {
+            if self.at("/*") {
+                depth += 1;
+                self.pos += 2;
+            } else if self.at("*/") {
+                depth -= 1;
+                self.pos += 2;
+                if depth == 0 {
+                    self.emit(start, Kind::Comment);
+                    return State::Normal;
+                }
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.emit(start, Kind::Comment);
+        State::BlockComment(depth)
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+fn rust(lexer: &mut Lexer, state: State) -> State {
+    let mut state = match state {
+        State::BlockComment(depth) => lexer.block_comment(0, depth),
+        State::String(_) => lexer.string(0, "\"", true, state),
+        State::RawString(hashes) => lexer.string(0, &raw_string_end(hashes), false, state),
+        _ => State::Normal,
+    };
+    while state == State::Normal && !lexer.done() {
+        let start = lexer.pos;
+        let c = lexer.chars[start];
+        if lexer.at("//") {
+            lexer.rest(start, Kind::Comment);
+        } else if lexer.at("/*") {
+            lexer.pos += 2;
+            state = lexer.block_comment(start, 1);
+        } else if let Some(hashes) = raw_string_start(lexer) {
+            let open = State::RawString(hashes);
+            state = lexer.string(start, &raw_string_end(hashes), false, open);
+        } else if c == '"' || lexer.at("b\"") {
+            lexer.pos += if c == '"' { 1 } else { 2 };
+            state = lexer.string(start, "\"", true, State::String('"'));
+        } else if c == '\'' {
+            char_or_lifetime(lexer);
+        } else if lexer.at("#[") || lexer.at("#![") {
+            let mut depth = 0;
+            while let Some(c) = lexer.peek() {
+                lexer.pos += 1;
+                match c {
+                    '[' => depth += 1,
+                    ']' if depth == 1 => break,
+                    ']' => depth -= 1,
+                    _ => (),
+                }
+            }
+            lexer.emit(start, Kind::Attribute);
+        } else if c.is_ascii_digit() {
+            lexer.number();
+            lexer.emit(start, Kind::Number);
+        } else if is_word_char(c) {
+            let word = lexer.word();
+            let kind = if RUST_KEYWORDS.contains(&word.as_str()) {
+                Some(Kind::Keyword)
+            } else if word == "true" || word == "false" {
+                Some(Kind::Constant)
+            } else if lexer.peek() == Some('!') && lexer.peek_at(1) != Some('=') {
+                lexer.pos += 1;
+                Some(Kind::Macro)
+            } else if word.starts_with(char::is_uppercase) {
+                Some(Kind::Type)
+            } else if lexer.peek() == Some('(') {
+                Some(Kind::Function)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                lexer.emit(start, kind);
+            }
+        } else {
+            lexer.pos += 1;
+        }
+    }
+    state
+}
+
+/// Moves past the `r#"` (or `br#"`) opening a raw string, returning its
+/// number of `#`s.
+fn raw_string_start(lexer: &mut Lexer) -> Option<u32> {
+    let prefix = if lexer.at("br") {
+        2
+    } else {
+        usize::from(lexer.at("r"))
+    };
+    if prefix == 0 {
+        return None;
+    }
+    let mut hashes = 0;
+    while lexer.peek_at(prefix + hashes) == Some('#') {
+        hashes += 1;
+    }
+    if lexer.peek_at(prefix + hashes) != Some('"') {
+        return None;
+    }
+    lexer.pos += prefix + hashes + 1;
+    u32::try_from(hashes).ok()
+}
+
+fn raw_string_end(hashes: u32) -> String {
+    format!("\"{}", "#".repeat(hashes as usize))
+}
+
+/// Tells `'a'` and `'\n'` from the lifetime `'a`.
+fn char_or_lifetime(lexer: &mut Lexer) {
+    let start = lexer.pos;
+    if lexer.peek_at(1) == Some('\\') {
+        lexer.pos += 3;
+        lexer.until("'", false);
+        lexer.emit(start, Kind::String);
+    } else if lexer.peek_at(2) == Some('\'') {
+        lexer.pos += 3;
+        lexer.emit(start, Kind::String);
+    } else {
+        lexer.pos += 1;
+        lexer.take_while(is_word_char);
+        lexer.emit(start, Kind::Type);
+    }
+}
+
+fn toml(lexer: &mut Lexer, state: State) -> State {
+    let continued = state != State::Normal;
+    let mut state = match state {
+        State::String(quote) => lexer.string(0, &quote.to_string().repeat(3), quote == '"', state),
+        _ => State::Normal,
+    };
+    if state != State::Normal {
+        return state;
+    }
+    lexer.take_while(char::is_whitespace);
+    if !continued && lexer.peek() == Some('[') {
+        let start = lexer.pos;
+        lexer.take_while(|c| c != ']');
+        lexer.take_while(|c| c == ']');
+        lexer.emit(start, Kind::Heading);
+    }
+    // Keys come first on a line and after `{` or `,` in inline tables.
+    let mut key_position = !continued && lexer.tokens.is_empty();
+    let mut nesting = Vec::new();
+    while state == State::Normal && !lexer.done() {
+        let start = lexer.pos;
+        let c = lexer.chars[start];
+        if c == '#' {
+            lexer.rest(start, Kind::Comment);
+        } else if lexer.at("\"\"\"") || lexer.at("'''") {
+            lexer.pos += 3;
+            let end = c.to_string().repeat(3);
+            state = lexer.string(start, &end, c == '"', State::String(c));
+        } else if c == '"' || c == '\'' {
+            lexer.pos += 1;
+            lexer.until(&c.to_string(), c == '"');
+            let kind = if key_position && followed_by_equals(lexer) {
+                Kind::Key
+            } else {
+                Kind::String
+            };
+            lexer.emit(start, kind);
+        } else if key_position && (is_word_char(c) || c == '-') {
+            lexer.take_while(|c| is_word_char(c) || c == '-' || c == '.');
+            lexer.emit(start, Kind::Key);
+        } else if c.is_ascii_digit()
+            || (matches!(c, '+' | '-') && lexer.peek_at(1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            lexer.pos += 1;
+            lexer.take_while(|c| {
+                c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.' | '+' | '-')
+            });
+            lexer.emit(start, Kind::Number);
+        } else if is_word_char(c) {
+            let kind = match lexer.word().as_str() {
+                "true" | "false" => Some(Kind::Constant),
+                "inf" | "nan" => Some(Kind::Number),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                lexer.emit(start, kind);
+            }
+        } else {
+            lexer.pos += 1;
+            match c {
+                '=' => key_position = false,
+                '{' | '[' => {
+                    nesting.push(c);
+                    key_position = c == '{';
+                }
+                '}' | ']' => {
+                    nesting.pop();
+                }
+                ',' => key_position = nesting.last() == Some(&'{'),
+                _ => (),
+            }
+        }
+    }
+    state
+}
+
+fn followed_by_equals(lexer: &Lexer) -> bool {
+    lexer.chars[lexer.pos..]
+        .iter()
+        .find(|c| !c.is_whitespace())
+        .is_some_and(|&c| c == '=')
+}
+
+fn json(lexer: &mut Lexer) -> State {
+    while !lexer.done() {
+        let start = lexer.pos;
+        let c = lexer.chars[start];
+        if c == '"' {
+            lexer.pos += 1;
+            lexer.until("\"", true);
+            let is_key = lexer.chars[lexer.pos..]
+                .iter()
+                .find(|c| !c.is_whitespace())
+                .is_some_and(|&c| c == ':');
+            lexer.emit(start, if is_key { Kind::Key } else { Kind::String });
+        } else if c == '-' || c.is_ascii_digit() {
+            lexer.pos += 1;
+            lexer.take_while(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+            lexer.emit(start, Kind::Number);
+        } else if c.is_alphabetic() {
+            if matches!(lexer.word().as_str(), "true" | "false" | "null") {
+                lexer.emit(start, Kind::Constant);
+            }
+        } else {
+            lexer.pos += 1;
+        }
+    }
+    State::Normal
+}
+
+fn markdown(lexer: &mut Lexer, state: State) -> State {
+    lexer.take_while(char::is_whitespace);
+    let fence = fence(lexer);
+    if let State::Fence(marker, count) = state {
+        lexer.rest(0, Kind::Code);
+        let closes = fence.is_some_and(|(c, n)| c == marker && n >= count);
+        return if closes { State::Normal } else { state };
+    }
+    if let Some((marker, count)) = fence {
+        lexer.rest(0, Kind::Code);
+        return State::Fence(marker, count);
+    }
+
+    let start = lexer.pos;
+    let hashes = lexer.chars[start..]
+        .iter()
+        .take_while(|&&c| c == '#')
+        .count();
+    if (1..=6).contains(&hashes) && lexer.peek_at(hashes).map_or(true, char::is_whitespace) {
+        lexer.rest(start, Kind::Heading);
+        return State::Normal;
+    }
+    let rule = lexer.chars[start..]
+        .iter()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    if rule.len() >= 3 && ['-', '*', '_'].iter().any(|m| rule.iter().all(|c| *c == m)) {
+        lexer.rest(start, Kind::Marker);
+        return State::Normal;
+    }
+    let bullet = matches!(lexer.peek(), Some('-' | '*' | '+')) && lexer.peek_at(1) == Some(' ');
+    if lexer.peek() == Some('>') || bullet {
+        lexer.pos += 1;
+        lexer.emit(start, Kind::Marker);
+    } else {
+        lexer.take_while(|c| c.is_ascii_digit());
+        if lexer.pos > start
+            && matches!(lexer.peek(), Some('.' | ')'))
+            && lexer.peek_at(1) == Some(' ')
+        {
+            lexer.pos += 1;
+            lexer.emit(start, Kind::Marker);
+        }
+    }
+
+    while !lexer.done() {
+        let start = lexer.pos;
+        let c = lexer.chars[start];
+        if c == '\\' {
+            lexer.pos = (lexer.pos + 2).min(lexer.chars.len());
+        } else if c == '`' {
+            lexer.take_while(|c| c == '`');
+            let ticks: String = lexer.chars[start..lexer.pos].iter().collect();
+            if let Some(end) = lexer.find(&ticks, lexer.pos) {
+                lexer.pos = end + ticks.len();
+                lexer.emit(start, Kind::Code);
+            }
+        } else if lexer.at("**") || lexer.at("__") {
+            let marker: String = lexer.chars[start..start + 2].iter().collect();
+            match lexer.find(&marker, start + 2) {
+                Some(end) => {
+                    lexer.pos = end + 2;
+                    lexer.emit(start, Kind::Strong);
+                }
+                None => lexer.pos += 2,
+            }
+        } else if (c == '*' || (c == '_' && !lexer.previous().is_some_and(is_word_char)))
+            && lexer.peek_at(1).is_some_and(|c| !c.is_whitespace())
+        {
+            match lexer.find(&c.to_string(), start + 1) {
+                Some(end) => {
+                    lexer.pos = end + 1;
+                    lexer.emit(start, Kind::Emphasis);
+                }
+                None => lexer.pos += 1,
+            }
+        } else if c == '[' {
+            let link_end = lexer
+                .find("](", start)
+                .and_then(|middle| lexer.find(")", middle));
+            match link_end {
+                Some(end) => {
+                    lexer.pos = end + 1;
+                    lexer.emit(start, Kind::Link);
+                }
+                None => lexer.pos += 1,
+            }
+        } else {
+            lexer.pos += 1;
+        }
+    }
+    State::Normal
+}
+
+/// The char and length of a code fence at the lexer's position.
+fn fence(lexer: &Lexer) -> Option<(char, usize)> {
+    let marker = lexer.peek().filter(|&c| c == '`' || c == '~')?;
+    let count = lexer.chars[lexer.pos..]
+        .iter()
+        .take_while(|&&c| c == marker)
+        .count();
+    (count >= 3).then_some((marker, count))
+}
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "select", "return", "local", "export", "readonly", "declare", "break",
+    "continue", "exit", "shift", "source", "unset", "trap",
+];
+
+fn shell(lexer: &mut Lexer, state: State) -> State {
+    let mut state = match state {
+        State::String('\'') => lexer.string(0, "'", false, state),
+        State::String(_) => lexer.string(0, "\"", true, state),
+        _ => State::Normal,
+    };
+    while state == State::Normal && !lexer.done() {
+        let start = lexer.pos;
+        let c = lexer.chars[start];
+        let word_start = !lexer
+            .previous()
+            .is_some_and(|c| is_word_char(c) || c == '-' || c == '/');
+        if c == '#'
+            && lexer
+                .previous()
+                .map_or(true, |c| c.is_whitespace() || c == ';')
+        {
+            lexer.rest(start, Kind::Comment);
+        } else if c == '\'' || c == '"' {
+            lexer.pos += 1;
+            state = lexer.string(start, &c.to_string(), c == '"', State::String(c));
+        } else if c == '\\' {
+            lexer.pos = (lexer.pos + 2).min(lexer.chars.len());
+        } else if c == '$' {
+            lexer.pos += 1;
+            match lexer.peek() {
+                Some('{') => {
+                    lexer.until("}", false);
+                }
+                Some(c) if is_word_char(c) => lexer.take_while(is_word_char),
+                Some('?' | '#' | '@' | '*' | '!' | '$' | '-') => lexer.pos += 1,
+                _ => (),
+            }
+            if lexer.pos > start + 1 {
+                lexer.emit(start, Kind::Variable);
+            }
+        } else if is_word_char(c) && word_start {
+            let word = lexer.word();
+            let kind = if SHELL_KEYWORDS.contains(&word.as_str()) {
+                Some(Kind::Keyword)
+            } else if word.chars().all(|c| c.is_ascii_digit()) {
+                Some(Kind::Number)
+            } else {
+                None
+            };
+            let word_end = !lexer
+                .peek()
+                .is_some_and(|c| c == '-' || c == '/' || c == '.');
+            if let Some(kind) = kind.filter(|_| word_end) {
+                lexer.emit(start, kind);
+            }
+        } else {
+            lexer.pos += 1;
+        }
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(language: Language, text: &str) -> Vec<(String, Kind)> {
+        let buffer = Buffer::from_text(text);
+        let lines: Vec<Vec<char>> = buffer.lines_from(0).map(|l| l.chars().collect()).collect();
+        Highlighter::new(language)
+            .highlight(&buffer, 0..buffer.line_count())
+            .into_iter()
+            .map(|(start, end, kind)| {
+                let text = lines[start.line][start.column..end.column].iter().collect();
+                (text, kind)
+            })
+            .collect()
+    }
+
+    fn fresh(buffer: &Buffer, lines: Range<usize>) -> Vec<(Cursor, Cursor, Kind)> {
+        Highlighter::new(Language::Rust).highlight(buffer, lines)
+    }
+
+    #[test]
+    fn picks_the_language_from_the_path() {
+        assert_eq!(
+            Language::from_path(Path::new("src/main.rs")),
+            Some(Language::Rust)
+        );
+        assert_eq!(
+            Language::from_path(Path::new("Cargo.lock")),
+            Some(Language::Toml)
+        );
+        assert_eq!(
+            Language::from_path(Path::new("/home/a/.bashrc")),
+            Some(Language::Shell)
+        );
+        assert_eq!(Language::from_path(Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn block_comments_span_lines() {
+        let tokens = kinds(Language::Rust, "/* a /* b */\nc */ fn");
+        assert_eq!(
+            tokens,
+            [
+                ("/* a /* b */".into(), Kind::Comment),
+                ("c */".into(), Kind::Comment),
+                ("fn".into(), Kind::Keyword),
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_toml_keys_from_values() {
+        let tokens = kinds(Language::Toml, "[package]\nname = \"x\" # c");
+        assert_eq!(
+            tokens,
+            [
+                ("[package]".into(), Kind::Heading),
+                ("name".into(), Kind::Key),
+                ("\"x\"".into(), Kind::String),
+                ("# c".into(), Kind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_fences_span_lines() {
+        let tokens = kinds(Language::Markdown, "```\n# not a heading\n```\n# heading");
+        let expected: Vec<(String, Kind)> = vec![
+            ("```".into(), Kind::Code),
+            ("# not a heading".into(), Kind::Code),
+            ("```".into(), Kind::Code),
+            ("# heading".into(), Kind::Heading),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn edits_reuse_the_start_states_below_them() {
+        let mut buffer = Buffer::from_text(&"fn a() {}\n/* b */\n".repeat(50));
+        let mut highlighter = Highlighter::new(Language::Rust);
+        highlighter.highlight(&buffer, 90..100);
+        let valid = highlighter.valid;
+        buffer.move_to(3, 0);
+        buffer.insert_text("let x = 1;\n");
+        assert_eq!(highlighter.highlight(&buffer, 6..8), fresh(&buffer, 6..8));
+        // The inserted line ends in the state the line below started in
+        // before, so the states known below the edit are still right.
+        assert_eq!(highlighter.valid, valid + 1);
+        assert_eq!(
+            highlighter.highlight(&buffer, 91..101),
+            fresh(&buffer, 91..101)
+        );
+    }
+
+    #[test]
+    fn edits_that_change_states_redo_the_lines_below() {
+        let mut buffer = Buffer::from_text(&"fn a() {}\n".repeat(20));
+        let mut highlighter = Highlighter::new(Language::Rust);
+        highlighter.highlight(&buffer, 15..20);
+        buffer.move_to(2, 0);
+        buffer.insert_text("/*");
+        assert_eq!(
+            highlighter.highlight(&buffer, 15..20),
+            fresh(&buffer, 15..20)
+        );
+        buffer.move_to(10, 0);
+        buffer.insert_text("*/");
+        assert_eq!(
+            highlighter.highlight(&buffer, 15..20),
+            fresh(&buffer, 15..20)
+        );
+        buffer.move_to(2, 2);
+        buffer.backspace();
+        buffer.backspace();
+        assert_eq!(highlighter.highlight(&buffer, 0..20), fresh(&buffer, 0..20));
+    }
+}
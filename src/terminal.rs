@@ -0,0 +1,45 @@
+use crossterm::{
+    event::{DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+use std::io::{self, stdout};
+
+pub fn enter() -> io::Result<()> {
+    enable_raw_mode()?;
+    stdout()
+        .execute(EnterAlternateScreen)?
+        .execute(EnableMouseCapture)?
+        .execute(EnableBracketedPaste)?;
+    Ok(())
+}
+
+/// Undoes `enter`. Every step is attempted even if an earlier one fails, so
+/// that the shell gets as much of its terminal back as possible.
+pub fn leave() -> io::Result<()> {
+    let raw_mode = disable_raw_mode();
+    let screen = stdout()
+        .execute(DisableBracketedPaste)
+        .and_then(|stdout| stdout.execute(DisableMouseCapture))
+        .and_then(|stdout| stdout.execute(LeaveAlternateScreen))
+        .map(|_| ());
+    raw_mode.and(screen)
+}
+
+/// Stops the process the way a shell's Ctrl+Z would, with the terminal
+/// handed back while it is stopped. Returns once the process is continued.
+#[cfg(unix)]
+pub fn suspend() -> io::Result<()> {
+    leave()?;
+    // SIGSTOP rather than SIGTSTP, since the app handles SIGTSTP itself.
+    // SAFETY: raise has no preconditions.
+    if unsafe { libc::raise(libc::SIGSTOP) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    enter()
+}
+
+#[cfg(not(unix))]
+pub fn suspend() -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use crate::{
+    app::AppState,
+    buffer::Cursor,
+    syntax::Kind,
+    wrap::{str_width, Row},
+};
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    prelude::Frame,
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState},
+};
+
+/// Where each part of the screen is drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct Areas {
+    /// The bordered box around the text.
+    pub editor: Rect,
+    /// The line numbers, left of the text.
+    pub gutter: Rect,
+    pub text: Rect,
+    pub scrollbar: Rect,
+    pub status: Rect,
+}
+
+/// Splits the screen into the editor, with the gutter and text area inside
+/// its border and the vertical scrollbar down its right edge, and the status
+/// line below it.
+pub fn layout(area: Rect, gutter_width: u16) -> Areas {
+    let [editor, status] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+    let inner = Block::default().borders(Borders::ALL).inner(editor);
+    let [gutter, text] =
+        Layout::horizontal([Constraint::Length(gutter_width), Constraint::Min(0)]).areas(inner);
+    let scrollbar = Rect {
+        x: editor.right().saturating_sub(1),
+        width: editor.width.min(1),
+        ..editor
+    };
+    Areas {
+        editor,
+        gutter,
+        text,
+        scrollbar,
+        status,
+    }
+}
+
+pub fn ui(frame: &mut Frame, state: &mut AppState) {
+    let areas = layout(frame.area(), state.gutter_width());
+    let text_area = areas.text;
+    state.set_viewport(text_area);
+    state.scrollbar_area = areas.scrollbar;
+
+    let rows = state.visible_rows();
+    state.set_content_width(&rows);
+    state.horizontal_scroll = state.horizontal_scroll.min(state.max_horizontal_scroll());
+    let gutter = gutter(state, &rows);
+    let highlights = highlights(state, &rows);
+    let render_lines: Vec<Line> = rows
+        .into_iter()
+        .map(|row| highlight(row, &highlights))
+        .collect();
+
+    let horizontal_scroll = u16::try_from(state.horizontal_scroll).unwrap_or(u16::MAX);
+    frame.render_widget(Block::default().borders(Borders::ALL), areas.editor);
+    frame.render_widget(Paragraph::new(gutter), areas.gutter);
+    frame.render_widget(
+        Paragraph::new(render_lines).scroll((0, horizontal_scroll)),
+        areas.text,
+    );
+    let mut scroll_state = ScrollbarState::new(state.max_scroll() + 1)
+        .viewport_content_length(state.viewport_height)
+        .position(state.scroll_position);
+    frame.render_stateful_widget(Scrollbar::default(), areas.editor, &mut scroll_state);
+
+    if state.max_horizontal_scroll() > 0 {
+        let mut scroll_state = ScrollbarState::new(state.max_horizontal_scroll() + 1)
+            .viewport_content_length(usize::from(text_area.width))
+            .position(state.horizontal_scroll);
+        // Leave the bottom-right corner to the vertical scrollbar's arrow.
+        let area = Rect {
+            width: areas.editor.width.saturating_sub(1),
+            ..areas.editor
+        };
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::HorizontalBottom),
+            area,
+            &mut scroll_state,
+        );
+    }
+
+    let area = areas.status;
+    let message = state.message.clone().unwrap_or_default();
+    if let Some(prompt) = &state.prompt {
+        let text = format!("{}{}", prompt.label(), prompt.input);
+        let cursor_x = area.x.saturating_add(str_width(&text) as u16);
+        status_line(frame, area, text, message);
+        frame.set_cursor_position(Position::new(cursor_x.min(area.right()), area.y));
+        return;
+    }
+    status_line(
+        frame,
+        area,
+        file_status(state, &message),
+        position_status(state),
+    );
+
+    let (row, x) = state.cursor_screen_offset();
+    let mut x = x.saturating_sub(state.horizontal_scroll);
+    if state.wrap {
+        // A row filled to the full width leaves no cell after its last char.
+        x = x.min(usize::from(text_area.width).saturating_sub(1));
+    }
+    if let Some(row) = row.checked_sub(state.scroll_position) {
+        if row < usize::from(text_area.height) && x < usize::from(text_area.width) {
+            frame.set_cursor_position(Position::new(
+                text_area.x + x as u16,
+                text_area.y + row as u16,
+            ));
+        }
+    }
+}
+
+/// The line number of each row, right-aligned and followed by a space.
+/// Rows that continue a wrapped line get none. Relative numbers count from
+/// the cursor's line, which shows its own number instead.
+fn gutter(state: &AppState, rows: &[Row]) -> Vec<Line<'static>> {
+    let width = usize::from(state.gutter_width()).saturating_sub(1);
+    let cursor_line = state.buffer.cursor().line;
+    rows.iter()
+        .map(|row| {
+            if row.range.start > 0 {
+                return Line::default();
+            }
+            let number = if state.relative_numbers && row.line != cursor_line {
+                row.line.abs_diff(cursor_line)
+            } else {
+                row.line + 1
+            };
+            let style = if row.line == cursor_line {
+                Style::new().yellow().bold()
+            } else {
+                Style::new().dark_gray()
+            };
+            Line::styled(format!("{number:>width$} "), style)
+        })
+        .collect()
+}
+
+/// The styled ranges of the visible text: search matches, and the
+/// selection over them.
+fn highlights(state: &mut AppState, rows: &[Row]) -> Vec<(Cursor, Cursor, Style)> {
+    let mut highlights = Vec::new();
+    if let (Some(highlighter), Some(first), Some(last)) =
+        (&mut state.highlighter, rows.first(), rows.last())
+    {
+        let tokens = highlighter.highlight(&state.buffer, first.line..last.line + 1);
+        highlights.extend(
+            tokens
+                .into_iter()
+                .map(|(start, end, kind)| (start, end, token_style(kind))),
+        );
+    }
+    if let (Some(search), Some(first), Some(last)) = (&state.search, rows.first(), rows.last()) {
+        let current = search.current();
+        let matches = search.matches_in(&state.buffer, first.line..last.line + 1);
+        highlights.extend(matches.into_iter().map(|(start, end)| {
+            let style = if current == Some((start, end)) {
+                Style::new().black().on_light_red()
+            } else {
+                Style::new().black().on_yellow()
+            };
+            (start, end, style)
+        }));
+    }
+    if let Some((start, end)) = state.buffer.selection_range() {
+        highlights.push((start, end, Style::new().reversed()));
+    }
+    highlights
+}
+
+fn token_style(kind: Kind) -> Style {
+    match kind {
+        Kind::Keyword => Style::new().magenta(),
+        Kind::Type => Style::new().yellow(),
+        Kind::Function => Style::new().blue(),
+        Kind::Macro | Kind::Attribute | Kind::Variable => Style::new().cyan(),
+        Kind::String | Kind::Code => Style::new().green(),
+        Kind::Number | Kind::Constant => Style::new().light_red(),
+        Kind::Comment => Style::new().dark_gray().italic(),
+        Kind::Key => Style::new().blue(),
+        Kind::Heading => Style::new().blue().bold(),
+        Kind::Marker => Style::new().yellow(),
+        Kind::Emphasis => Style::new().italic(),
+        Kind::Strong => Style::new().bold(),
+        Kind::Link => Style::new().blue().underlined(),
+    }
+}
+
+/// Draws a row with the parts of it covered by `highlights` styled.
+fn highlight(row: Row, highlights: &[(Cursor, Cursor, Style)]) -> Line<'static> {
+    let mut styles = vec![Style::new(); row.range.len()];
+    for &(start, end, style) in highlights {
+        if row.line < start.line || row.line > end.line {
+            continue;
+        }
+        let from = if row.line == start.line {
+            start.column
+        } else {
+            0
+        };
+        let to = if row.line == end.line {
+            end.column
+        } else {
+            usize::MAX
+        };
+        let from = from.clamp(row.range.start, row.range.end) - row.range.start;
+        let to = to.clamp(row.range.start, row.range.end) - row.range.start;
+        for char_style in styles.iter_mut().take(to).skip(from) {
+            *char_style = char_style.patch(style);
+        }
+    }
+    let mut spans: Vec<Span> = Vec::new();
+    for (c, style) in row.text.chars().zip(styles) {
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push(c),
+            _ => spans.push(Span::styled(c.to_string(), style)),
+        }
+    }
+    Line::from(spans)
+}
+
+/// Draws `left` and `right` at either end of the status line.
+fn status_line(frame: &mut Frame, area: Rect, left: String, right: String) {
+    let right_width = str_width(&right) as u16;
+    let [left_area, right_area] =
+        Layout::horizontal([Constraint::Min(0), Constraint::Length(right_width)]).areas(area);
+    frame.render_widget(Paragraph::new(left).reversed(), left_area);
+    frame.render_widget(Paragraph::new(right).reversed(), right_area);
+}
+
+/// The mode, file name and modified flag, then any message.
+fn file_status(state: &AppState, message: &str) -> String {
+    let mut status = String::from(" ");
+    if let Some(label) = state.vim.as_ref().and_then(|vim| vim.mode().label()) {
+        status.push_str(label);
+        status.push(' ');
+    }
+    match &state.path {
+        Some(path) => status.push_str(&path.display().to_string()),
+        None => status.push_str("untitled"),
+    }
+    if state.modified {
+        status.push_str(" [+]");
+    }
+    if !message.is_empty() {
+        status.push_str("  ");
+        status.push_str(message);
+    }
+    status
+}
+
+/// Where the cursor and viewport are in the file, and how it is stored.
+/// Files are always read and written as UTF-8.
+fn position_status(state: &AppState) -> String {
+    let cursor = state.buffer.cursor();
+    let lines = state.buffer.lines_in_file();
+    let max_scroll = state.max_scroll();
+    let scroll = if max_scroll == 0 {
+        "All".to_string()
+    } else if state.scroll_position == 0 {
+        "Top".to_string()
+    } else if state.scroll_position >= max_scroll {
+        "Bot".to_string()
+    } else {
+        format!("{}%", state.scroll_position * 100 / max_scroll)
+    };
+    format!(
+        " Ln {}, Col {}  {} {}  {}  UTF-8  {} ",
+        cursor.line + 1,
+        cursor.column + 1,
+        lines,
+        if lines == 1 { "line" } else { "lines" },
+        scroll,
+        state.buffer.line_ending(),
+    )
+}
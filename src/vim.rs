@@ -0,0 +1,342 @@
+use crate::keymap::{Action, Keymap};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Mode {
+    /// What the status line shows. Normal mode shows nothing, as in Vim.
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            Mode::Normal => None,
+            Mode::Insert => Some("-- INSERT --"),
+            Mode::Visual => Some("-- VISUAL --"),
+        }
+    }
+}
+
+/// The largest count a command takes. Counts repeat actions one by one, so
+/// a larger one could keep the editor busy for minutes.
+const MAX_COUNT: usize = 100_000;
+
+/// How a command affects what `.` repeats.
+enum Outcome {
+    Motion,
+    Change,
+    Insert,
+    Pending,
+}
+
+/// Vim-style modal editing. Insert mode, and keys Vim has no use for, go
+/// through the keymap as usual.
+#[derive(Debug, Default)]
+pub struct Vim {
+    mode: Mode,
+    count: Option<usize>,
+    operator: Option<(char, Option<usize>)>,
+    /// The keys of the change being typed, and of the last complete one,
+    /// which `.` replays.
+    keys: Vec<KeyEvent>,
+    last_change: Vec<KeyEvent>,
+    replaying: bool,
+}
+
+impl Vim {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn handle(&mut self, key: KeyEvent, keymap: &mut Keymap) -> Vec<Action> {
+        if !self.replaying {
+            self.keys.push(key);
+        }
+        if self.mode == Mode::Insert {
+            if key.code == KeyCode::Esc {
+                self.mode = Mode::Normal;
+                self.finish_change();
+                return Vec::new();
+            }
+            return keymap.resolve(key).into_iter().collect();
+        }
+
+        let plain = !key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let c = match key.code {
+            KeyCode::Char(c) if plain => Some(c),
+            _ => None,
+        };
+        if let Some(digit) = c.and_then(|c| c.to_digit(10)) {
+            if digit != 0 || self.count.is_some() {
+                let count = self.count.unwrap_or(0);
+                let count = count.saturating_mul(10).saturating_add(digit as usize);
+                self.count = Some(count.min(MAX_COUNT));
+                return Vec::new();
+            }
+        }
+        let given = self.count.take();
+        let (actions, outcome) = match self.operator.take() {
+            Some((operator, count)) => Self::operator(operator, c, count.or(given)),
+            None => self.command(key, c, given, keymap),
+        };
+        match outcome {
+            Outcome::Motion => self.keys.clear(),
+            Outcome::Change => self.finish_change(),
+            Outcome::Insert => self.mode = Mode::Insert,
+            Outcome::Pending => (),
+        }
+        actions
+    }
+
+    fn command(
+        &mut self,
+        key: KeyEvent,
+        c: Option<char>,
+        given: Option<usize>,
+        keymap: &mut Keymap,
+    ) -> (Vec<Action>, Outcome) {
+        let count = given.unwrap_or(1);
+        let repeat = |action| vec![action; count];
+        let visual = self.mode == Mode::Visual;
+        let plain = !key
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let motion = match (c, key.code) {
+            _ if !plain => None,
+            (Some('h'), _) | (_, KeyCode::Left | KeyCode::Backspace) => Some(Action::CursorLeft),
+            (Some('l' | ' '), _) | (_, KeyCode::Right) => Some(Action::CursorRight),
+            (Some('j'), _) | (_, KeyCode::Down) => Some(Action::CursorDown),
+            (Some('k'), _) | (_, KeyCode::Up) => Some(Action::CursorUp),
+            (Some('w'), _) => Some(Action::WordForward),
+            (Some('b'), _) => Some(Action::WordBackward),
+            (Some('e'), _) => Some(Action::WordEnd),
+            _ => None,
+        };
+        if let Some(motion) = motion {
+            return (repeat(motion), Outcome::Motion);
+        }
+
+        match c {
+            Some('0' | '^') => (vec![Action::LineStart], Outcome::Motion),
+            Some('$') => (vec![Action::LineEnd], Outcome::Motion),
+            Some('G') => (
+                vec![given.map_or(Action::DocumentEnd, Action::JumpToLine)],
+                Outcome::Motion,
+            ),
+            Some(operator @ ('g' | 'd' | 'y')) if !visual || operator == 'g' => {
+                self.operator = Some((operator, given));
+                (Vec::new(), Outcome::Pending)
+            }
+            Some('d' | 'x') if visual => self.leave_visual(Action::DeleteSelection),
+            Some('y') if visual => self.leave_visual(Action::YankSelection),
+            Some('v') if visual => self.leave_visual(Action::Cancel),
+            Some(':') if visual => self.leave_visual(Action::CommandPrompt),
+            _ if visual && key.code == KeyCode::Esc => self.leave_visual(Action::Cancel),
+            Some('x') => (repeat(Action::DeleteInLine), Outcome::Change),
+            Some('X') => (repeat(Action::BackspaceInLine), Outcome::Change),
+            Some('p') => (repeat(Action::Paste), Outcome::Change),
+            Some('P') => (repeat(Action::PasteBefore), Outcome::Change),
+            Some('i') if !visual => (Vec::new(), Outcome::Insert),
+            Some('a') if !visual => (vec![Action::Append], Outcome::Insert),
+            Some('I') if !visual => (vec![Action::LineStart], Outcome::Insert),
+            Some('A') if !visual => (vec![Action::LineEnd], Outcome::Insert),
+            Some('o') if !visual => (vec![Action::OpenLineBelow], Outcome::Insert),
+            Some('O') if !visual => (vec![Action::OpenLineAbove], Outcome::Insert),
+            Some('v') => {
+                self.mode = Mode::Visual;
+                (vec![Action::StartSelection], Outcome::Motion)
+            }
+            Some('u') => (repeat(Action::Undo), Outcome::Motion),
+            Some('.') => (self.repeat_change(given, keymap), Outcome::Motion),
+            Some(':') => (vec![Action::CommandPrompt], Outcome::Motion),
+            Some('/') => (vec![Action::Search], Outcome::Motion),
+            Some('n') => (repeat(Action::SearchNext), Outcome::Motion),
+            Some('N') => (repeat(Action::SearchPrevious), Outcome::Motion),
+            _ if key.code == KeyCode::Char('r')
+                && key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                (repeat(Action::Redo), Outcome::Motion)
+            }
+            // Enter goes to the start of the next line, like `+`. That is a
+            // whole line down even when lines wrap, which `j` is not.
+            _ if c == Some('+') || (plain && key.code == KeyCode::Enter) => {
+                let mut actions = [Action::LineEnd, Action::CursorRight].repeat(count);
+                actions.push(Action::LineStart);
+                (actions, Outcome::Motion)
+            }
+            _ => {
+                let action = keymap.resolve(key).filter(|action| !action.edits());
+                (action.into_iter().collect(), Outcome::Motion)
+            }
+        }
+    }
+
+    fn operator(operator: char, c: Option<char>, count: Option<usize>) -> (Vec<Action>, Outcome) {
+        match (operator, c) {
+            ('d', Some('d')) => (
+                vec![Action::DeleteLines(count.unwrap_or(1))],
+                Outcome::Change,
+            ),
+            ('y', Some('y')) => (vec![Action::YankLines(count.unwrap_or(1))], Outcome::Motion),
+            ('g', Some('g')) => (
+                vec![count.map_or(Action::DocumentStart, Action::JumpToLine)],
+                Outcome::Motion,
+            ),
+            _ => (Vec::new(), Outcome::Motion),
+        }
+    }
+
+    fn leave_visual(&mut self, action: Action) -> (Vec<Action>, Outcome) {
+        self.mode = Mode::Normal;
+        (vec![action], Outcome::Motion)
+    }
+
+    /// Replays the last change. A count replaces the one it was typed with.
+    fn repeat_change(&mut self, count: Option<usize>, keymap: &mut Keymap) -> Vec<Action> {
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            let typed_count = keys
+                .iter()
+                .take_while(|key| matches!(key.code, KeyCode::Char('0'..='9')))
+                .count();
+            let digits: Vec<_> = count
+                .to_string()
+                .chars()
+                .map(|digit| KeyEvent::new(KeyCode::Char(digit), KeyModifiers::NONE))
+                .collect();
+            keys.splice(..typed_count, digits);
+        }
+        self.replaying = true;
+        let actions = keys
+            .into_iter()
+            .flat_map(|key| self.handle(key, keymap))
+            .collect();
+        self.replaying = false;
+        actions
+    }
+
+    fn finish_change(&mut self) {
+        if !self.replaying {
+            self.last_change = std::mem::take(&mut self.keys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `keys` to Vim, one plain key per char with `\u{1b}` for Esc,
+    /// and collects the actions.
+    fn press(vim: &mut Vim, keys: &str) -> Vec<Action> {
+        let mut keymap = Keymap::default();
+        keys.chars()
+            .flat_map(|c| {
+                let code = match c {
+                    '\u{1b}' => KeyCode::Esc,
+                    c => KeyCode::Char(c),
+                };
+                vim.handle(KeyEvent::new(code, KeyModifiers::NONE), &mut keymap)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_repeat_commands() {
+        let mut vim = Vim::default();
+        assert_eq!(press(&mut vim, "3x"), vec![Action::DeleteInLine; 3]);
+        assert_eq!(press(&mut vim, "2j"), vec![Action::CursorDown; 2]);
+        assert_eq!(press(&mut vim, "10k"), vec![Action::CursorUp; 10]);
+    }
+
+    #[test]
+    fn operators_take_counts() {
+        let mut vim = Vim::default();
+        assert_eq!(press(&mut vim, "2dd"), vec![Action::DeleteLines(2)]);
+        assert_eq!(press(&mut vim, "d3d"), vec![Action::DeleteLines(3)]);
+        assert_eq!(press(&mut vim, "yy"), vec![Action::YankLines(1)]);
+        assert_eq!(press(&mut vim, "dj"), vec![]);
+    }
+
+    #[test]
+    fn goes_to_lines() {
+        let mut vim = Vim::default();
+        assert_eq!(press(&mut vim, "5G"), vec![Action::JumpToLine(5)]);
+        assert_eq!(press(&mut vim, "G"), vec![Action::DocumentEnd]);
+        assert_eq!(press(&mut vim, "gg"), vec![Action::DocumentStart]);
+        assert_eq!(press(&mut vim, "7gg"), vec![Action::JumpToLine(7)]);
+    }
+
+    #[test]
+    fn dot_repeats_an_insert() {
+        let mut vim = Vim::default();
+        assert_eq!(
+            press(&mut vim, "ahi\u{1b}"),
+            vec![Action::Append, Action::Insert('h'), Action::Insert('i')]
+        );
+        assert_eq!(vim.mode(), Mode::Normal);
+        assert_eq!(
+            press(&mut vim, "j."),
+            vec![
+                Action::CursorDown,
+                Action::Append,
+                Action::Insert('h'),
+                Action::Insert('i')
+            ]
+        );
+        assert_eq!(vim.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn a_count_replaces_the_one_dot_repeats() {
+        let mut vim = Vim::default();
+        press(&mut vim, "2x");
+        assert_eq!(press(&mut vim, "."), vec![Action::DeleteInLine; 2]);
+        assert_eq!(press(&mut vim, "3."), vec![Action::DeleteInLine; 3]);
+        assert_eq!(
+            press(&mut vim, "dd3."),
+            vec![Action::DeleteLines(1), Action::DeleteLines(3)]
+        );
+    }
+
+    #[test]
+    fn motions_are_not_repeated_by_dot() {
+        let mut vim = Vim::default();
+        press(&mut vim, "x");
+        press(&mut vim, "3w");
+        assert_eq!(press(&mut vim, "."), vec![Action::DeleteInLine]);
+    }
+
+    #[test]
+    fn visual_mode_acts_on_the_selection() {
+        let mut vim = Vim::default();
+        assert_eq!(
+            press(&mut vim, "vld"),
+            vec![
+                Action::StartSelection,
+                Action::CursorRight,
+                Action::DeleteSelection
+            ]
+        );
+        assert_eq!(vim.mode(), Mode::Normal);
+        press(&mut vim, "v");
+        assert_eq!(vim.mode(), Mode::Visual);
+        assert_eq!(press(&mut vim, "\u{1b}"), vec![Action::Cancel]);
+        assert_eq!(vim.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn huge_counts_are_clamped() {
+        let mut vim = Vim::default();
+        assert_eq!(press(&mut vim, "99999999999999999999999j").len(), MAX_COUNT);
+        assert_eq!(
+            press(&mut vim, "99999999999999999999999dd"),
+            vec![Action::DeleteLines(MAX_COUNT)]
+        );
+    }
+}
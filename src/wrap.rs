@@ -0,0 +1,217 @@
+use crate::buffer::{Buffer, LineEdit};
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
+
+/// A visual row on screen: the chars `range` of logical line `line`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub line: usize,
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+pub fn str_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Splits a line into rows of at most `width` display columns, breaking
+/// after whitespace where possible. Returns the char index each row starts
+/// at, so the result is never empty.
+pub fn row_starts(line: &str, width: usize) -> Vec<usize> {
+    let width = width.max(1);
+    let mut starts = vec![0];
+    let mut row_width = 0;
+    let mut break_point: Option<(usize, usize)> = None;
+    for (i, c) in line.chars().enumerate() {
+        let w = char_width(c);
+        while row_width + w > width && starts.last() < Some(&i) {
+            match break_point.take() {
+                Some((index, used)) => {
+                    starts.push(index);
+                    row_width -= used;
+                }
+                None => {
+                    starts.push(i);
+                    row_width = 0;
+                }
+            }
+        }
+        row_width += w;
+        if c.is_whitespace() {
+            break_point = Some((i + 1, row_width));
+        }
+    }
+    starts
+}
+
+/// The display width of the chars in `range`.
+pub fn display_width(line: &str, range: Range<usize>) -> usize {
+    line.chars()
+        .skip(range.start)
+        .take(range.len())
+        .map(char_width)
+        .sum()
+}
+
+/// The char index in `range` that is drawn at display column `x` of the
+/// row, or the end of the range when `x` is past the last char.
+pub fn column_at(line: &str, range: Range<usize>, x: usize) -> usize {
+    let mut used = 0;
+    for (i, c) in line.chars().enumerate().skip(range.start).take(range.len()) {
+        used += char_width(c);
+        if used > x {
+            return i;
+        }
+    }
+    range.end
+}
+
+/// The first visual row of every line in wrapped mode, cached for one
+/// buffer revision and width so that scrolling is a binary search.
+#[derive(Debug, Default)]
+pub struct WrapIndex {
+    key: Option<(u64, usize)>,
+    first_rows: Vec<usize>,
+}
+
+impl WrapIndex {
+    pub fn update(&mut self, buffer: &Buffer, width: usize) {
+        let key = Some((buffer.revision(), width));
+        if self.key == key {
+            return;
+        }
+        match buffer.last_edit() {
+            Some(edit) if self.key == Some((edit.base_revision, width)) => {
+                self.patch(buffer, edit, width);
+            }
+            _ => self.rebuild(buffer, width),
+        }
+        self.key = key;
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.first_rows.last().copied().unwrap_or(0)
+    }
+
+    pub fn first_row(&self, line: usize) -> usize {
+        self.first_rows[line.min(self.first_rows.len() - 1)]
+    }
+
+    /// The line containing visual `row`, and the row's offset in that line.
+    pub fn line_at_row(&self, row: usize) -> (usize, usize) {
+        let line = self
+            .first_rows
+            .partition_point(|&first| first <= row)
+            .saturating_sub(1)
+            .min(self.first_rows.len().saturating_sub(2));
+        (line, row - self.first_rows[line])
+    }
+
+    fn rebuild(&mut self, buffer: &Buffer, width: usize) {
+        self.first_rows.clear();
+        self.first_rows.reserve(buffer.line_count() + 1);
+        let mut row = 0;
+        for line in buffer.lines_from(0) {
+            self.first_rows.push(row);
+            row += row_starts(&line, width).len();
+        }
+        self.first_rows.push(row);
+    }
+
+    /// Re-wraps only the lines an edit replaced and shifts the rows after
+    /// them, which keeps typing in a huge document cheap.
+    fn patch(&mut self, buffer: &Buffer, edit: &LineEdit, width: usize) {
+        let start_row = self.first_rows[edit.old.start];
+        let old_rows = self.first_rows[edit.old.end] - start_row;
+        let mut row = start_row;
+        let mut first_rows = Vec::with_capacity(edit.new.len());
+        for line in buffer.lines_from(edit.new.start).take(edit.new.len()) {
+            first_rows.push(row);
+            row += row_starts(&line, width).len();
+        }
+        let new_rows = row - start_row;
+        self.first_rows.splice(edit.old.clone(), first_rows);
+        for first in &mut self.first_rows[edit.new.end..] {
+            *first = *first - old_rows + new_rows;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(buffer: &Buffer, width: usize) -> WrapIndex {
+        let mut index = WrapIndex::default();
+        index.update(buffer, width);
+        index
+    }
+
+    #[test]
+    fn breaks_rows_after_whitespace() {
+        assert_eq!(row_starts("hello world", 8), [0, 6]);
+        assert_eq!(row_starts("one two three", 8), [0, 8]);
+    }
+
+    #[test]
+    fn breaks_long_words_anywhere() {
+        assert_eq!(row_starts("abcdefghij", 4), [0, 4, 8]);
+    }
+
+    #[test]
+    fn wide_chars_do_not_straddle_rows() {
+        assert_eq!(row_starts("ab日本", 3), [0, 2, 3]);
+    }
+
+    #[test]
+    fn empty_lines_take_one_row() {
+        assert_eq!(row_starts("", 10), [0]);
+    }
+
+    #[test]
+    fn zero_width_wraps_every_char() {
+        assert_eq!(row_starts("abc", 0), [0, 1, 2]);
+    }
+
+    #[test]
+    fn patching_matches_a_rebuild() {
+        let mut buffer = Buffer::from_text("short\na line that wraps\n\nend\n");
+        let mut patched = index(&buffer, 6);
+        buffer.move_to(1, 6);
+        buffer.insert_text("that wraps again\nand ");
+        patched.update(&buffer, 6);
+        assert_eq!(patched.first_rows, index(&buffer, 6).first_rows);
+        buffer.move_to(3, 0);
+        buffer.backspace();
+        patched.update(&buffer, 6);
+        assert_eq!(patched.first_rows, index(&buffer, 6).first_rows);
+    }
+
+    #[test]
+    fn patching_edits_made_as_one_matches_a_rebuild() {
+        let mut buffer = Buffer::from_text("short\na line that wraps\n\nend\n");
+        let mut patched = index(&buffer, 6);
+        buffer.edit_as_one(|buffer| {
+            buffer.move_to(3, 1);
+            buffer.insert_text(" and more\nlines");
+            buffer.move_to(0, 5);
+            buffer.delete();
+        });
+        patched.update(&buffer, 6);
+        assert_eq!(patched.first_rows, index(&buffer, 6).first_rows);
+    }
+
+    #[test]
+    fn finds_the_line_at_a_row() {
+        let buffer = Buffer::from_text("a\nabc def ghi\nb");
+        let index = index(&buffer, 4);
+        assert_eq!(index.row_count(), 5);
+        assert_eq!(index.line_at_row(0), (0, 0));
+        assert_eq!(index.line_at_row(3), (1, 2));
+        assert_eq!(index.line_at_row(4), (2, 0));
+    }
+}